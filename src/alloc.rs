@@ -0,0 +1,114 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+/// Part of session, to which allocations are attributed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocPhase {
+    /// Hello and capability negotiation
+    Handshake,
+    /// Reading of file commands and their content from git
+    Read,
+    /// Sending of processor output to git
+    Write,
+    /// Everything else, including processor own work
+    Other,
+}
+
+thread_local! {
+    static PHASE: Cell<AllocPhase> = const { Cell::new(AllocPhase::Other) };
+    static COUNTS: [Cell<u64>; 4] = const {
+        [Cell::new(0), Cell::new(0), Cell::new(0), Cell::new(0)]
+    };
+}
+
+impl AllocPhase {
+    /// Attribute allocations on current thread to this phase, until guard is dropped
+    pub(crate) fn enter(self) -> PhaseGuard {
+        let previous = PHASE.with(|phase| phase.replace(self));
+        PhaseGuard(previous)
+    }
+}
+
+pub(crate) struct PhaseGuard(AllocPhase);
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        PHASE.with(|phase| phase.set(self.0));
+    }
+}
+
+/// Global allocator wrapper, counting allocations made on each thread per [`AllocPhase`]
+///
+/// Counts are only collected once installed by filter binary:
+/// `#[global_allocator] static ALLOC: CountingAllocator = CountingAllocator(System);`.
+/// Allocations of threads spawned by processor are not attributed to session
+pub struct CountingAllocator<A = System>(pub A);
+
+fn count_allocation() {
+    // Thread locals may be already destroyed during thread exit
+    let _ = PHASE.try_with(|phase| {
+        let _ = COUNTS.try_with(|counts| {
+            let count = &counts[phase.get() as usize];
+            count.set(count.get() + 1);
+        });
+    });
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        self.0.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        self.0.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        self.0.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+}
+
+/// Count of allocations (including reallocations) per phase,
+/// see [`crate::SessionSummary::allocations`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocationStats {
+    pub handshake: u64,
+    pub read: u64,
+    pub write: u64,
+    pub other: u64,
+}
+
+impl AllocationStats {
+    /// Allocations made on current thread so far
+    pub fn current() -> Self {
+        COUNTS.with(|counts| Self {
+            handshake: counts[AllocPhase::Handshake as usize].get(),
+            read: counts[AllocPhase::Read as usize].get(),
+            write: counts[AllocPhase::Write as usize].get(),
+            other: counts[AllocPhase::Other as usize].get(),
+        })
+    }
+
+    /// Allocations made after `earlier` snapshot
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            handshake: self.handshake - earlier.handshake,
+            read: self.read - earlier.read,
+            write: self.write - earlier.write,
+            other: self.other - earlier.other,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.handshake + self.read + self.write + self.other
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::{Result, Write};
+
+use crate::{ext::WriteExt, ProcessingType};
+
+/// Set of capabilities, negotiated during handshake
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities {
+    pub clean: bool,
+    pub smudge: bool,
+    pub delay: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Self {
+            clean: true,
+            smudge: true,
+            delay: true,
+        }
+    }
+
+    /// Is capability for specified processing type present?
+    pub fn supports(&self, process_type: ProcessingType) -> bool {
+        match process_type {
+            ProcessingType::Clean => self.clean,
+            ProcessingType::Smudge => self.smudge,
+        }
+    }
+
+    /// Capabilities present in both sets
+    pub fn intersection(&self, other: &Self) -> Self {
+        Self {
+            clean: self.clean && other.clean,
+            smudge: self.smudge && other.smudge,
+            delay: self.delay && other.delay,
+        }
+    }
+
+    /// Names of present capabilities, in order they are sent over the wire
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.clean {
+            names.push("clean");
+        }
+        if self.smudge {
+            names.push("smudge");
+        }
+        if self.delay {
+            names.push("delay");
+        }
+        names
+    }
+
+    /// Add capability by name, returns false if capability is unknown
+    pub fn insert(&mut self, name: &str) -> bool {
+        match name {
+            "clean" => self.clean = true,
+            "smudge" => self.smudge = true,
+            "delay" => self.delay = true,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Write `capability=` lines, without terminating flush
+    pub(crate) fn write<W: Write>(&self, output: &mut W) -> Result<()> {
+        for name in self.names() {
+            output.pkt_text_write(&format!("capability={}", name))?;
+        }
+        Ok(())
+    }
+}
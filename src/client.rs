@@ -0,0 +1,64 @@
+use std::io::{Read, Result, Write};
+
+use crate::{
+    ext::{ReadExt, WriteExt},
+    parse_error, Capabilities,
+};
+
+/// Client side of the handshake, as performed by git
+///
+/// Useful for test clients and proxies
+pub struct ClientHandshake {
+    capabilities: Capabilities,
+}
+
+impl ClientHandshake {
+    /// Offer specified capabilities to the server
+    pub fn new(capabilities: Capabilities) -> Self {
+        Self { capabilities }
+    }
+
+    /// Perform handshake, returns capabilities advertised by server
+    pub fn perform<R: Read, W: Write>(
+        &self,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<Capabilities> {
+        output.pkt_text_write("git-filter-client")?;
+        output.pkt_text_write("version=2")?;
+        output.pkt_end()?;
+
+        let mut buf = Vec::new();
+        if input.pkt_text_read(&mut buf)? != Some("git-filter-server") {
+            return Err(parse_error!("bad server prelude"));
+        }
+        if input.pkt_text_read(&mut buf)? != Some("version=2") {
+            return Err(parse_error!("unknown version"));
+        }
+        if input.pkt_text_read(&mut buf)?.is_some() {
+            return Err(parse_error!("unexpected text after server hello"));
+        }
+
+        self.capabilities.write(output)?;
+        output.pkt_end()?;
+
+        let mut advertised = Capabilities::default();
+        while let Some(line) = input.pkt_text_read(&mut buf)? {
+            let capability = line
+                .strip_prefix("capability=")
+                .ok_or_else(|| parse_error!(format!("expected capability, got {:?}", line)))?;
+            if !advertised.insert(capability) {
+                return Err(parse_error!(format!(
+                    "server advertised unknown capability: {}",
+                    capability
+                )));
+            }
+        }
+        if advertised.intersection(&self.capabilities) != advertised {
+            return Err(parse_error!(
+                "server advertised capability, which wasn't offered"
+            ));
+        }
+        Ok(advertised)
+    }
+}
@@ -0,0 +1,30 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Source of time for server, see [`crate::GitFilterServer::clock`]
+///
+/// Replaceable, so command timing and delay polling can be tested without real sleeps.
+/// [`crate::TimeoutWriter`] is the exception, it always waits for real time, because
+/// write it interrupts is blocked in the OS, not in the server
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Wait for given duration, used between polls of delayed files
+    fn sleep(&self, duration: Duration);
+}
+
+/// Real monotonic clock, used by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    io::{Read, Result},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::parse_error;
+
+/// Limits for [`BoundedDecompressor`]
+#[derive(Debug, Clone, Copy)]
+pub struct DecompressionLimits {
+    /// Max allowed ratio of decompressed size to compressed size
+    pub max_ratio: u64,
+    /// Ratio is only checked once this many bytes are decompressed,
+    /// as stream headers make ratio meaningless for tiny inputs
+    pub ratio_grace: u64,
+    /// Max allowed decompressed size
+    pub max_output_size: Option<u64>,
+}
+
+impl Default for DecompressionLimits {
+    fn default() -> Self {
+        Self {
+            max_ratio: 100,
+            ratio_grace: 1024 * 1024,
+            max_output_size: None,
+        }
+    }
+}
+
+/// Counts bytes, consumed by decoder from compressed input
+pub struct CountingReader<R> {
+    read: R,
+    consumed: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.read.read(buf)?;
+        self.consumed.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// Wraps streaming decoder of untrusted input, failing with InvalidData once
+/// output grows too large, either in absolute size or relative to consumed input
+///
+/// Guards working tree against zip-bomb-style blobs
+pub struct BoundedDecompressor<D> {
+    decoder: D,
+    consumed: Arc<AtomicU64>,
+    produced: u64,
+    limits: DecompressionLimits,
+}
+
+impl<D> BoundedDecompressor<D> {
+    /// Create decoder over counted compressed input with `make_decoder`
+    pub fn new<R: Read>(
+        input: R,
+        limits: DecompressionLimits,
+        make_decoder: impl FnOnce(CountingReader<R>) -> Result<D>,
+    ) -> Result<Self> {
+        let consumed = Arc::new(AtomicU64::new(0));
+        let decoder = make_decoder(CountingReader {
+            read: input,
+            consumed: consumed.clone(),
+        })?;
+        Ok(Self {
+            decoder,
+            consumed,
+            produced: 0,
+            limits,
+        })
+    }
+
+    /// Count of decompressed bytes returned so far
+    pub fn produced(&self) -> u64 {
+        self.produced
+    }
+
+    /// Count of compressed bytes consumed by decoder so far
+    pub fn consumed(&self) -> u64 {
+        self.consumed.load(Ordering::Relaxed)
+    }
+}
+
+impl<D: Read> Read for BoundedDecompressor<D> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.decoder.read(buf)?;
+        self.produced = self.produced.saturating_add(read as u64);
+        if let Some(max_output_size) = self.limits.max_output_size {
+            if self.produced > max_output_size {
+                return Err(parse_error!("max decompressed size exceeded"));
+            }
+        }
+        if self.produced > self.limits.ratio_grace
+            && self.produced > self.consumed().saturating_mul(self.limits.max_ratio)
+        {
+            return Err(parse_error!("max decompression ratio exceeded"));
+        }
+        Ok(read)
+    }
+}
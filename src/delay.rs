@@ -0,0 +1,58 @@
+use std::io::{Read, Result, Write};
+
+use crate::{
+    ext::{strip_newline, ReadExt, WriteExt},
+    parse_error, Pathname,
+};
+
+/// Write successful response to `list_available_blobs`, as done by server
+///
+/// Empty list tells git, that there is nothing more to deliver.
+/// Pathnames are sent as raw bytes, same as git sent them
+pub fn write_available_blobs<W, I, S>(output: &mut W, pathnames: I) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = S>,
+    S: AsRef<[u8]>,
+{
+    let mut line = Vec::new();
+    for pathname in pathnames {
+        line.clear();
+        line.extend_from_slice(b"pathname=");
+        line.extend_from_slice(pathname.as_ref());
+        line.push(b'\n');
+        output.pkt_bin_write(&line)?;
+    }
+    output.pkt_end()?;
+    output.pkt_text_write("status=success")?;
+    output.pkt_end()
+}
+
+/// Read response to `list_available_blobs`, as done by git
+///
+/// Fails if server responded with any status other than success
+pub fn read_available_blobs<R: Read>(input: &mut R) -> Result<Vec<Pathname>> {
+    let mut buf = Vec::new();
+    let mut pathnames = Vec::new();
+    while let Some(line) = input.pkt_bin_read(&mut buf)? {
+        let line = strip_newline(line)?;
+        let pathname = line.strip_prefix(b"pathname=").ok_or_else(|| {
+            parse_error!(format!("expected pathname, got {:?}", line.escape_ascii()))
+        })?;
+        pathnames.push(Pathname::from_bytes(pathname));
+    }
+    let mut status = None;
+    while let Some(line) = input.pkt_text_read(&mut buf)? {
+        if let Some(value) = line.strip_prefix("status=") {
+            status = Some(value.to_owned());
+        }
+    }
+    match status.as_deref() {
+        Some("success") => Ok(pathnames),
+        Some(status) => Err(parse_error!(format!(
+            "listing available blobs failed with status {}",
+            status
+        ))),
+        None => Err(parse_error!("missing status")),
+    }
+}
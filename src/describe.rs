@@ -0,0 +1,22 @@
+/// Capabilities and limits of configured server, see [`crate::GitFilterServer::describe`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Description {
+    /// Capabilities, which would be advertised to git, if git offers them
+    pub capabilities: Vec<&'static str>,
+    /// Max size of data in single pkt-line
+    pub max_pkt_size: usize,
+    /// Max size of data accepted for single file, if limited
+    pub max_input_size: Option<u64>,
+    /// Max count of pkt records accepted for single file, if limited
+    pub max_records_per_file: Option<u64>,
+    /// Max size of data produced for single file, if limited
+    pub max_output_size: Option<u64>,
+}
+
+#[cfg(feature = "json")]
+impl Description {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("description is always serializable")
+    }
+}
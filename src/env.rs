@@ -0,0 +1,25 @@
+use anyhow::{bail, Result};
+use std::{collections::HashMap, ffi::OsString};
+
+/// Capture values of environment variables, failing with list of missing ones
+///
+/// Intended for use in [`crate::Processor::start_session`]
+pub fn require_env(names: &[&str]) -> Result<HashMap<String, OsString>> {
+    let mut values = HashMap::new();
+    let mut missing = Vec::new();
+    for name in names {
+        match std::env::var_os(name) {
+            Some(value) => {
+                values.insert(name.to_string(), value);
+            }
+            None => missing.push(*name),
+        }
+    }
+    if !missing.is_empty() {
+        bail!(
+            "missing required environment variables: {}",
+            missing.join(", ")
+        );
+    }
+    Ok(values)
+}
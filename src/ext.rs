@@ -1,9 +1,22 @@
-use std::io::{Read, Result, Write};
+use std::io::{Error, ErrorKind, Read, Result, Write};
 
 use crate::parse_error;
 
 pub const MAX_PKT_SIZE: usize = 65516;
 
+/// Repeat operation, while it fails with Interrupted (EINTR)
+///
+/// `read_exact`, `read_to_end` and `write_all` already do this, but `flush` doesn't
+pub fn retry_interrupted<T>(mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    loop {
+        match f() {
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Reading is retried on Interrupted, signal delivered to process doesn't break session
 pub trait ReadExt {
     fn pkt_bin_read<'b>(&mut self, out: &'b mut Vec<u8>) -> Result<Option<&'b [u8]>>;
     fn pkt_text_read<'b>(&mut self, out: &'b mut Vec<u8>) -> Result<Option<&'b str>>;
@@ -15,7 +28,7 @@ impl<R: Read> ReadExt for R {
         self.read_exact(&mut len_hex)?;
 
         let mut len_bytes = [0; 2];
-        hex::decode_to_slice(&len_hex, &mut len_bytes).map_err(|_| parse_error!("bad hex len"))?;
+        hex::decode_to_slice(len_hex, &mut len_bytes).map_err(|_| parse_error!("bad hex len"))?;
 
         let mut len = u16::from_be_bytes(len_bytes) as usize;
         if len == 0 {
@@ -28,9 +41,13 @@ impl<R: Read> ReadExt for R {
             return Err(parse_error!("packet size is zero"));
         }
 
-        out.reserve(len.saturating_sub(out.len()));
-        out.resize(len, 0);
-        self.read_exact(&mut out[..len])?;
+        // read_to_end fills spare capacity directly, without zeroing it first
+        out.clear();
+        out.reserve(len);
+        self.take(len as u64).read_to_end(out)?;
+        if out.len() != len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated packet"));
+        }
 
         Ok(Some(out))
     }
@@ -40,15 +57,19 @@ impl<R: Read> ReadExt for R {
         } else {
             return Ok(None);
         };
-        if !s.ends_with(b"\n") {
-            return Err(parse_error!("string should end with \n"));
-        }
         Ok(Some(
-            std::str::from_utf8(&s[..s.len() - 1]).map_err(|_| parse_error!("bad utf-8"))?,
+            std::str::from_utf8(strip_newline(s)?).map_err(|_| parse_error!("bad utf-8"))?,
         ))
     }
 }
 
+/// Text packet payload without trailing newline, not validated as UTF-8
+pub fn strip_newline(s: &[u8]) -> Result<&[u8]> {
+    s.strip_suffix(b"\n")
+        .ok_or_else(|| parse_error!("string should end with \n"))
+}
+
+/// Writing is retried on Interrupted, same as [`ReadExt`]
 pub trait WriteExt {
     fn pkt_bin_write(&mut self, data: &[u8]) -> Result<()>;
     fn pkt_text_write(&mut self, data: &str) -> Result<()>;
@@ -57,10 +78,10 @@ pub trait WriteExt {
 
 impl<W: Write> WriteExt for W {
     fn pkt_bin_write(&mut self, data: &[u8]) -> Result<()> {
-        for chunk in data.chunks(MAX_PKT_SIZE - 4) {
+        for chunk in data.chunks(MAX_PKT_SIZE) {
             let len_bytes = (chunk.len() as u16 + 4).to_be_bytes();
             let mut len_hex = [0; 4];
-            hex::encode_to_slice(&len_bytes, &mut len_hex).unwrap();
+            hex::encode_to_slice(len_bytes, &mut len_hex).unwrap();
             self.write_all(&len_hex)?;
             self.write_all(chunk)?;
         }
@@ -73,7 +94,7 @@ impl<W: Write> WriteExt for W {
     }
     fn pkt_end(&mut self) -> Result<()> {
         self.write_all(b"0000")?;
-        self.flush()?;
+        retry_interrupted(|| self.flush())?;
         Ok(())
     }
 }
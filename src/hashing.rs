@@ -0,0 +1,57 @@
+use digest::DynDigest;
+use std::io::{Read, Result};
+
+/// Feeds everything read through several digests at once, counting read bytes
+///
+/// Avoids extra passes over input, when more than one digest (or size) is needed
+pub struct MultiHashingReader<R> {
+    read: R,
+    digests: Vec<Box<dyn DynDigest + Send>>,
+    read_bytes: u64,
+}
+
+impl<R> MultiHashingReader<R> {
+    pub fn new(read: R) -> Self {
+        Self {
+            read,
+            digests: Vec::new(),
+            read_bytes: 0,
+        }
+    }
+
+    /// Add digest, which will be fed with data read after this call
+    pub fn with_digest(self, digest: impl DynDigest + Send + 'static) -> Self {
+        self.with_boxed_digest(Box::new(digest))
+    }
+
+    pub fn with_boxed_digest(mut self, digest: Box<dyn DynDigest + Send>) -> Self {
+        self.digests.push(digest);
+        self
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.read_bytes
+    }
+
+    /// Returns digests in order they were added, and count of read bytes
+    pub fn finalize(self) -> (Vec<Box<[u8]>>, u64) {
+        (
+            self.digests
+                .into_iter()
+                .map(|mut d| d.finalize_reset())
+                .collect(),
+            self.read_bytes,
+        )
+    }
+}
+
+impl<R: Read> Read for MultiHashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.read.read(buf)?;
+        for digest in self.digests.iter_mut() {
+            digest.update(&buf[..read]);
+        }
+        self.read_bytes = self.read_bytes.saturating_add(read as u64);
+        Ok(read)
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{Error, ErrorKind, Result, Write},
+    path::Path,
+    process::{self, Command},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::parse_error;
+
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Make sure gitattributes file at `path` assigns `filter_name` to `pattern`
+///
+/// Returns true if file was modified
+pub fn ensure_gitattributes(
+    path: impl AsRef<Path>,
+    pattern: &str,
+    filter_name: &str,
+) -> Result<bool> {
+    ensure_gitattributes_many(path, &[(pattern, filter_name)])
+}
+
+/// Make sure gitattributes file at `path` assigns filters to all of `(pattern, filter_name)` entries
+///
+/// Entries which are already present are skipped, missing entries are appended
+/// in single atomic write. Returns true if file was modified
+pub fn ensure_gitattributes_many(path: impl AsRef<Path>, entries: &[(&str, &str)]) -> Result<bool> {
+    let path = path.as_ref();
+    for (pattern, filter_name) in entries {
+        if pattern.is_empty() || pattern.contains(char::is_whitespace) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("bad gitattributes pattern: {:?}", pattern),
+            ));
+        }
+        if filter_name.is_empty() || filter_name.contains(char::is_whitespace) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("bad filter name: {:?}", filter_name),
+            ));
+        }
+    }
+
+    let mut content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let mut present: Vec<(String, String)> = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let pattern = parts.next().expect("line is not empty");
+        for filter_name in parts.filter_map(|attr| attr.strip_prefix("filter=")) {
+            present.push((pattern.to_owned(), filter_name.to_owned()));
+        }
+    }
+
+    let mut changed = false;
+    for (pattern, filter_name) in entries {
+        if present
+            .iter()
+            .any(|(p, f)| p == pattern && f == filter_name)
+        {
+            continue;
+        }
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&format!("{} filter={}\n", pattern, filter_name));
+        present.push((pattern.to_string(), filter_name.to_string()));
+        changed = true;
+    }
+    if !changed {
+        return Ok(false);
+    }
+
+    let file_name = path.file_name().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "gitattributes path has no file name",
+        )
+    })?;
+    // Unique per process and call, so concurrent installers don't clobber each other's file
+    let mut tmp_name = file_name.to_owned();
+    tmp_name.push(format!(
+        ".{}.{}.tmp",
+        process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = path.with_file_name(tmp_name);
+    let mut tmp = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)?;
+    let written = tmp.write_all(content.as_bytes());
+    // Closed before rename, which fails for open files on windows
+    drop(tmp);
+    if let Err(e) = written.and_then(|()| fs::rename(&tmp_path, path)) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    Ok(true)
+}
+
+/// Read `filter.<name>.*` entries from git config of current repository
+///
+/// Keys are returned without `filter.<name>.` prefix, for repeated keys last value wins,
+/// valueless keys are treated as `true`, as git does for booleans
+pub fn read_filter_config(name: &str) -> Result<HashMap<String, String>> {
+    let mut regex = String::from("^filter\\.");
+    for c in name.chars() {
+        if "\\.[]{}()*+?^$|".contains(c) {
+            regex.push('\\');
+        }
+        regex.push(c);
+    }
+    regex.push_str("\\.");
+
+    let output = Command::new("git")
+        .args(["config", "-z", "--get-regexp", &regex])
+        .output()?;
+    match output.status.code() {
+        Some(0) => {}
+        // No matching keys
+        Some(1) => return Ok(HashMap::new()),
+        _ => {
+            return Err(Error::other(format!(
+                "git config failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+    }
+
+    parse_filter_config(name, &output.stdout)
+}
+
+/// Parse output of `git config -z --get-regexp` for `filter.<name>.*` keys,
+/// as done by [`read_filter_config`]
+///
+/// Fails on keys of other filters, or without anything after prefix
+pub fn parse_filter_config(name: &str, output: &[u8]) -> Result<HashMap<String, String>> {
+    let prefix = format!("filter.{}.", name);
+    let output =
+        std::str::from_utf8(output).map_err(|_| parse_error!("bad utf-8 in git config"))?;
+    let mut config = HashMap::new();
+    for entry in output.split_terminator('\0') {
+        let (key, value) = match entry.split_once('\n') {
+            Some((key, value)) => (key, value),
+            None => (entry, "true"),
+        };
+        let key = key
+            .strip_prefix(&prefix)
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| parse_error!(format!("unexpected git config key: {:?}", key)))?;
+        config.insert(key.to_owned(), value.to_owned());
+    }
+    Ok(config)
+}
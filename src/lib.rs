@@ -1,13 +1,74 @@
-use std::io::{ErrorKind, Read, Result, Write};
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind, Read, Result, Write},
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use ext::{ReadExt, WriteExt};
+use ext::{retry_interrupted, strip_newline, ReadExt, WriteExt, MAX_PKT_SIZE};
 
-use tracing::{error, info_span};
-use util::{ReadPktUntilFlush, WritePkt};
+use tracing::{error, field, info, info_span, trace, warn, Span};
+use util::{BoundedBuffer, WritePkt};
+#[cfg(feature = "alloc-stats")]
+mod alloc;
+mod capabilities;
+mod client;
+mod clock;
+mod decompress;
+mod delay;
+mod describe;
+mod env;
 pub(crate) mod ext;
+#[cfg(feature = "hashing")]
+mod hashing;
+#[cfg(feature = "install")]
+mod install;
+#[cfg(feature = "tcp")]
+mod listener;
+mod pathname;
+mod pipeline;
 mod processor;
+#[cfg(all(feature = "rlimit", unix))]
+mod rlimit;
+mod split;
+mod summary;
+mod symmetric;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod upload;
+mod user;
 mod util;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "alloc-stats")]
+pub use alloc::{AllocPhase, AllocationStats, CountingAllocator};
+pub use capabilities::*;
+pub use client::*;
+pub use clock::*;
+pub use decompress::*;
+pub use delay::*;
+pub use describe::*;
+pub use env::*;
+#[cfg(feature = "hashing")]
+pub use hashing::*;
+#[cfg(feature = "install")]
+pub use install::*;
+#[cfg(feature = "tcp")]
+pub use listener::*;
+pub use pathname::*;
+pub use pipeline::*;
 pub use processor::*;
+#[cfg(all(feature = "rlimit", unix))]
+pub use rlimit::*;
+pub use split::*;
+pub use summary::*;
+pub use symmetric::*;
+pub use upload::*;
+pub use user::*;
+pub use util::{ReadPktUntilFlush, TeeReader, TimeoutWriter};
+#[cfg(feature = "wasm")]
+pub use wasm::{WasmInstance, WasmLimits, WasmProcessor, WasmiInstance};
 
 #[macro_export]
 macro_rules! parse_error {
@@ -16,20 +77,446 @@ macro_rules! parse_error {
     };
 }
 
-pub struct GitFilterServer<P>(P);
+/// Run processor callback, with panic converted to error if `catch_panics` is set
+fn run_processor<T>(
+    catch_panics: bool,
+    panicked: &mut bool,
+    f: impl FnOnce() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    if !catch_panics {
+        return f();
+    }
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            *panicked = true;
+            let message = if let Some(message) = payload.downcast_ref::<&str>() {
+                message
+            } else if let Some(message) = payload.downcast_ref::<String>() {
+                message.as_str()
+            } else {
+                "unknown panic payload"
+            };
+            Err(anyhow::anyhow!("processor panicked: {}", message))
+        }
+    }
+}
+
+/// Terminate response to file command, after processor returned
+///
+/// If processor produced no output, deferred `status=success` wasn't sent yet
+///
+/// Empty successful output is sent as status, flush, flush (no content), flush (keep status),
+/// git reads it as empty file. There is no way to send empty data explicitly: git treats
+/// empty `0004` packet the same as flush, so it would end content early and break framing
+fn end_response<W: Write>(output: &mut W, status_sent: bool, success: bool) -> Result<()> {
+    #[cfg(feature = "alloc-stats")]
+    let _phase = AllocPhase::Write.enter();
+    if !status_sent {
+        if !success {
+            output.pkt_text_write("status=error")?;
+            return output.pkt_end();
+        }
+        output.pkt_text_write("status=success")?;
+        output.pkt_end()?;
+    }
+    // End of content
+    output.pkt_end()?;
+    if success {
+        // Keep status
+        output.pkt_end()
+    } else {
+        output.pkt_text_write("status=error")?;
+        output.pkt_end()
+    }
+}
+
+/// Compare digest of consumed input with expected hex checksum, if both are present
+#[cfg(feature = "hashing")]
+fn check_digest<R>(input: MultiHashingReader<R>, expected: Option<&str>) -> anyhow::Result<()> {
+    let (digests, _) = input.finalize();
+    if let (Some(expected), Some(actual)) = (expected, digests.first()) {
+        let actual = hex::encode(actual);
+        if !actual.eq_ignore_ascii_case(expected) {
+            anyhow::bail!("checksum mismatch: expected {}, got {}", expected, actual);
+        }
+    }
+    Ok(())
+}
+
+/// Fail file, which input was cut by limit or read error
+///
+/// Processor might ignore such error and report success on truncated data,
+/// while the rest of file is still left in stream
+fn check_terminated<R>(input: &ReadPktUntilFlush<R>) -> anyhow::Result<()> {
+    if !input.terminated_cleanly() {
+        return Err(parse_error!("input was not terminated by flush").into());
+    }
+    Ok(())
+}
+
+/// Check of complete processor output, before it is sent to git,
+/// see [`GitFilterServer::validate_output`]
+pub type OutputValidator = Arc<dyn Fn(&[u8]) -> anyhow::Result<()> + Send + Sync>;
+
+/// Validate output, which was buffered for validator, and send it
+fn send_validated<W: Write>(
+    validated: Option<(OutputValidator, BoundedBuffer)>,
+    output: &mut W,
+) -> anyhow::Result<()> {
+    if let Some((validator, buffer)) = validated {
+        validator(&buffer.data).map_err(|e| e.context("output validation failed"))?;
+        output.write_all(&buffer.data)?;
+    }
+    Ok(())
+}
+
+/// Records duration of command on its span, once command is done
+struct CommandTimer<'s> {
+    span: &'s Span,
+    clock: Arc<dyn Clock>,
+    started: Instant,
+}
+
+impl<'s> CommandTimer<'s> {
+    fn start(span: &'s Span, clock: Arc<dyn Clock>) -> Self {
+        let started = clock.now();
+        Self {
+            span,
+            clock,
+            started,
+        }
+    }
+}
+
+impl Drop for CommandTimer<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.clock.now().saturating_duration_since(self.started);
+        let elapsed_us = elapsed.as_micros() as u64;
+        self.span.record("elapsed_us", &elapsed_us);
+        trace!(parent: self.span, elapsed_us, "command finished");
+    }
+}
+
+/// How session ended, when it didn't fail
+enum SessionEnd {
+    /// Client closed the stream
+    Eof,
+    /// Client sent end of session marker, see [`GitFilterServer::communicate_multiplexed`]
+    Closed,
+    /// Server stopped reading after failed file, rest of the stream can't be parsed
+    Abandoned,
+}
+
+struct DelayedFile {
+    process_type: ProcessingType,
+    /// Was already listed as available to git
+    available: bool,
+    /// Processor never produced it, and it was listed only to be failed
+    abandoned: bool,
+}
+
+/// Behavior for processors, which returned successfully without reading whole input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnreadInput {
+    /// Discard the rest of input, so framing stays intact, and log a warning
+    Drain,
+    /// Panic, for catching under-reading processors in tests
+    Panic,
+}
+
+pub struct GitFilterServer<P> {
+    processor: P,
+    max_input_size: Option<u64>,
+    max_records_per_file: Option<u64>,
+    max_output_size: Option<u64>,
+    write_timeout: Option<Duration>,
+    catch_panics: bool,
+    unread_input: UnreadInput,
+    report_errors_to_user: bool,
+    available_batch_size: usize,
+    available_poll_interval: Duration,
+    max_empty_polls: u64,
+    fixed_record_size: Option<usize>,
+    output_buffer_size: Option<usize>,
+    size_hint_tolerance: Option<u64>,
+    #[cfg(feature = "hashing")]
+    checksum_digest: Option<fn() -> Box<dyn digest::DynDigest + Send>>,
+    #[cfg(all(feature = "rlimit", unix))]
+    resource_limits: Option<ResourceLimits>,
+    clock: Arc<dyn Clock>,
+    output_validators: HashMap<ProcessingType, OutputValidator>,
+    metrics_sink: Option<Box<dyn MetricsSink>>,
+    delayed: HashMap<Pathname, DelayedFile>,
+    summary: SessionSummary,
+}
 
 impl<P> GitFilterServer<P> {
     pub fn new(processor: P) -> Self {
-        Self(processor)
+        Self {
+            processor,
+            max_input_size: None,
+            max_records_per_file: None,
+            max_output_size: None,
+            write_timeout: None,
+            catch_panics: false,
+            unread_input: UnreadInput::Drain,
+            report_errors_to_user: false,
+            available_batch_size: 1,
+            available_poll_interval: Duration::from_millis(10),
+            max_empty_polls: 30_000,
+            fixed_record_size: None,
+            output_buffer_size: None,
+            size_hint_tolerance: None,
+            #[cfg(feature = "hashing")]
+            checksum_digest: None,
+            #[cfg(all(feature = "rlimit", unix))]
+            resource_limits: None,
+            clock: Arc::new(SystemClock),
+            output_validators: HashMap::new(),
+            metrics_sink: None,
+            delayed: HashMap::new(),
+            summary: SessionSummary::default(),
+        }
+    }
+
+    /// Limit size of data git is allowed to send for single file
+    pub fn max_input_size(mut self, size: u64) -> Self {
+        self.max_input_size = Some(size);
+        self
+    }
+
+    /// Limit count of pkt records git is allowed to send for single file
+    ///
+    /// Guards against large amount of tiny records, which are accepted by size limit
+    pub fn max_records_per_file(mut self, records: u64) -> Self {
+        self.max_records_per_file = Some(records);
+        self
+    }
+
+    /// Limit size of data processor is allowed to send for single file
+    ///
+    /// Exceeding write fails, and file is reported to git with `status=error`
+    pub fn max_output_size(mut self, size: u64) -> Self {
+        self.max_output_size = Some(size);
+        self
+    }
+
+    /// Fail with TimedOut instead of hanging, if git stops reading filter output
+    ///
+    /// Only applies to [`GitFilterServer::communicate_stdio`], wrap output in
+    /// [`TimeoutWriter`] to get the same behavior for other streams
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Convert processor panics to `status=error` for current file, and continue session
+    ///
+    /// Disabled by default, processor state after panic might be inconsistent,
+    /// so only enable this if processor can handle that
+    pub fn catch_panics(mut self, catch_panics: bool) -> Self {
+        self.catch_panics = catch_panics;
+        self
+    }
+
+    /// What to do with input, which processor left unread, default is [`UnreadInput::Drain`]
+    ///
+    /// Processor may legitimately ignore the rest of input, i.e once it knows the result,
+    /// but it also may be a bug in read loop
+    pub fn unread_input(mut self, unread_input: UnreadInput) -> Self {
+        self.unread_input = unread_input;
+        self
+    }
+
+    /// Show reason of every failed file to user of git command, via [`warn_user`]
+    ///
+    /// Protocol v2 has no way to pass error details: git only looks at `status=` key of
+    /// status list, and silently ignores any other key, so reason can't be sent in-band.
+    /// Git passes filter stderr through to terminal, which is the only channel left
+    pub fn report_errors_to_user(mut self, report: bool) -> Self {
+        self.report_errors_to_user = report;
+        self
+    }
+
+    /// Respond to `list_available_blobs` only once this many delayed files are ready,
+    /// or once there is no more pending files
+    ///
+    /// Git requests every listed blob, and then asks for the list again, so smaller
+    /// batches reduce latency of first blobs, while larger batches reduce count of rounds.
+    /// Default is 1, i.e respond as soon as anything is ready
+    pub fn available_batch_size(mut self, size: usize) -> Self {
+        self.available_batch_size = size.max(1);
+        self
+    }
+
+    /// Delay between [`Processor::get_available`] calls, which returned nothing
+    pub fn available_poll_interval(mut self, interval: Duration) -> Self {
+        self.available_poll_interval = interval;
+        self
+    }
+
+    /// Split processor output into records of exactly this size, except for the last one
+    ///
+    /// Makes output bytes independent of how processor writes and flushes data,
+    /// which is useful for transcript snapshots. Size is clamped to max pkt payload size
+    pub fn fixed_record_size(mut self, size: usize) -> Self {
+        self.fixed_record_size = Some(size);
+        self
+    }
+
+    /// Collect up to this many bytes of processor output before sending it to git,
+    /// by default output is sent one record at a time
+    ///
+    /// Results in fewer, larger writes, i.e for output over network, but doesn't change
+    /// framing. Memory used for output is bounded by this size either way
+    pub fn output_buffer_size(mut self, size: usize) -> Self {
+        self.output_buffer_size = Some(size);
+        self
+    }
+
+    /// Fail file, if processor writes more than `tolerance` bytes over its
+    /// [`Processor::output_size_hint`]
+    ///
+    /// Hint is advisory by default, this catches processors, which committed to a size,
+    /// but produced more. Files without hint are not checked
+    pub fn strict_size_hint(mut self, tolerance: u64) -> Self {
+        self.size_hint_tolerance = Some(tolerance);
+        self
+    }
+
+    /// Give up on delayed files, once [`Processor::get_available`] returned no new
+    /// pending file this many times in a row
+    ///
+    /// Pathnames, which weren't delayed or are already listed, don't count as progress
+    ///
+    /// Outstanding files are then listed to git, and answered with `status=error`,
+    /// so git doesn't wait for them forever. Default is 30000, i.e 5 minutes with default
+    /// [`GitFilterServer::available_poll_interval`]
+    pub fn max_empty_polls(mut self, rounds: u64) -> Self {
+        self.max_empty_polls = rounds;
+        self
+    }
+
+    /// Verify input against `checksum=` header of file command, using given digest
+    ///
+    /// Hex digest of data received from git is compared after processor returns,
+    /// and mismatching file is reported with `status=error`. Commands without header
+    /// aren't checked. Delayed files are verified once scheduled, mismatching file is
+    /// cancelled via [`Processor::cancel_scheduled`] instead of being delayed
+    #[cfg(feature = "hashing")]
+    pub fn verify_checksum(mut self, digest: fn() -> Box<dyn digest::DynDigest + Send>) -> Self {
+        self.checksum_digest = Some(digest);
+        self
+    }
+
+    /// Check shape of output for given processing type, i.e that clean produced a pointer
+    ///
+    /// Output is then buffered in memory instead of being streamed, validator is called
+    /// once processor returns, and failure is reported to git with `status=error`.
+    /// Catches processors, which mixed up clean and smudge, or produce malformed output.
+    ///
+    /// Buffer is bounded by [`GitFilterServer::max_output_size`], so set it when output
+    /// may be large, other output limits only apply once validated output is sent
+    pub fn validate_output(
+        mut self,
+        process_type: ProcessingType,
+        validator: impl Fn(&[u8]) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.output_validators
+            .insert(process_type, Arc::new(validator));
+        self
+    }
+
+    /// Report summary of every session to given sink, once session ends
+    pub fn metrics_sink(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.metrics_sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Use given clock for command durations and poll intervals, instead of [`SystemClock`]
+    ///
+    /// Output timeout of [`TimeoutWriter`] isn't affected, and is measured in real time
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Limit resources of filter process, applied by [`GitFilterServer::run_stdio_main`]
+    /// before serving git
+    ///
+    /// Other ways of running server should call [`ResourceLimits::apply`] themselves
+    #[cfg(all(feature = "rlimit", unix))]
+    pub fn resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.resource_limits = Some(limits);
+        self
+    }
+
+    /// Summary of last (or currently running) session
+    pub fn summary(&self) -> &SessionSummary {
+        &self.summary
     }
 }
 
 impl<P: Processor> GitFilterServer<P> {
+    /// Processing types, which would be advertised to git if requested
+    pub fn supported_types(&self) -> Vec<ProcessingType> {
+        ProcessingType::ALL
+            .iter()
+            .copied()
+            .filter(|t| self.processor.supports_processing(*t))
+            .collect()
+    }
+
+    /// Capabilities, which would be advertised to git if requested
+    pub fn supported_capabilities(&self) -> Capabilities {
+        Capabilities {
+            clean: self.processor.supports_processing(ProcessingType::Clean),
+            smudge: self.processor.supports_processing(ProcessingType::Smudge),
+            delay: true,
+        }
+    }
+
+    /// Describe capabilities and limits of this server, without talking to git
+    pub fn describe(&self) -> Description {
+        Description {
+            capabilities: self.supported_capabilities().names(),
+            max_pkt_size: MAX_PKT_SIZE,
+            max_input_size: self.max_input_size,
+            max_records_per_file: self.max_records_per_file,
+            max_output_size: self.max_output_size,
+        }
+    }
+
+    /// Print description as JSON to stdout, for use in `--describe` CLI modes
+    #[cfg(feature = "json")]
+    pub fn describe_stdio(&self) -> Result<()> {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        writeln!(stdout, "{}", self.describe().to_json())?;
+        stdout.flush()
+    }
+
     fn communicate_internal<R: Read, W: Write>(
         &mut self,
         mut input: &mut R,
         mut output: &mut W,
-    ) -> Result<()> {
+        multiplexed: bool,
+    ) -> Result<SessionEnd> {
+        self.summary = SessionSummary {
+            idempotent: self
+                .supported_types()
+                .into_iter()
+                .filter(|t| self.processor.is_idempotent(*t))
+                .collect(),
+            ..Default::default()
+        };
+        self.delayed.clear();
+        if let Err(e) = self.processor.start_session() {
+            return Err(io::Error::other(format!("session startup failed: {:#}", e)));
+        }
+        #[cfg(feature = "alloc-stats")]
+        let handshake_phase = AllocPhase::Handshake.enter();
         let mut buf = Vec::new();
         {
             if input.pkt_text_read(&mut buf)? != Some("git-filter-client") {
@@ -38,7 +525,7 @@ impl<P: Processor> GitFilterServer<P> {
             if input.pkt_text_read(&mut buf)? != Some("version=2") {
                 return Err(parse_error!("unknown version"));
             }
-            if input.pkt_text_read(&mut buf)? != None {
+            if input.pkt_text_read(&mut buf)?.is_some() {
                 return Err(parse_error!("unexpected text after client hello"));
             }
         }
@@ -47,46 +534,75 @@ impl<P: Processor> GitFilterServer<P> {
             output.pkt_text_write("version=2")?;
             output.pkt_end()?;
         }
-        {
-            let mut filter = false;
-            let mut smudge = false;
-            let mut delay = false;
-            while let Some(command) = input.pkt_text_read(&mut buf)? {
-                match command {
-                    "capability=clean" => filter = true,
-                    "capability=smudge" => smudge = true,
-                    "capability=delay" => delay = true,
-                    _ => {}
+        let negotiated = {
+            let mut offered = Capabilities::default();
+            while let Some(line) = input.pkt_text_read(&mut buf)? {
+                let capability = line.strip_prefix("capability=").unwrap_or(line);
+                if !offered.insert(capability) {
+                    info!(capability, "git offered unknown capability");
+                    self.summary
+                        .unknown_capabilities
+                        .push(capability.to_owned());
                 }
             }
-            if filter && self.0.supports_processing(ProcessingType::Clean) {
-                output.pkt_text_write("capability=clean")?;
-            }
-            if smudge && self.0.supports_processing(ProcessingType::Smudge) {
-                output.pkt_text_write("capability=smudge")?;
-            }
-            if delay {
-                output.pkt_text_write("capability=delay")?;
-            }
+            let negotiated = self.supported_capabilities().intersection(&offered);
+            negotiated.write(&mut output)?;
             output.pkt_end()?;
-        }
+            negotiated
+        };
+        #[cfg(feature = "alloc-stats")]
+        drop(handshake_phase);
 
         let mut waiting_for_blobs = false;
         loop {
             let mut command = None;
             let mut pathname = None;
+            // Absent `can-delay=1` means file can't be delayed, even if capability
+            // was negotiated, some git versions don't send it for every command
             let mut can_delay = false;
-            while let Some(input) = input.pkt_text_read(&mut buf)? {
+            #[cfg(feature = "hashing")]
+            let mut checksum = None;
+            #[cfg(feature = "alloc-stats")]
+            let header_phase = AllocPhase::Read.enter();
+            let mut header_lines = 0;
+            while let Some(line) = input.pkt_bin_read(&mut buf)? {
+                header_lines += 1;
+                let line = strip_newline(line)?;
+                // Pathname is not required to be valid UTF-8
+                if let Some(pathname_val) = line.strip_prefix(b"pathname=") {
+                    pathname = Some(Pathname::from_bytes(pathname_val));
+                    continue;
+                }
+                let input = std::str::from_utf8(line).map_err(|_| parse_error!("bad utf-8"))?;
+                #[cfg(feature = "hashing")]
+                if let Some(checksum_val) = input.strip_prefix("checksum=") {
+                    checksum = Some(checksum_val.to_owned());
+                    continue;
+                }
                 if let Some(command_val) = input.strip_prefix("command=") {
                     command = Some(command_val.to_owned());
-                } else if let Some(pathname_val) = input.strip_prefix("pathname=") {
-                    pathname = Some(pathname_val.to_owned())
                 } else if input == "can-delay=1" {
-                    can_delay = true;
+                    if negotiated.delay {
+                        can_delay = true;
+                    } else {
+                        warn!("git sent can-delay, but delay capability wasn't negotiated");
+                    }
                 }
             }
+            #[cfg(feature = "alloc-stats")]
+            drop(header_phase);
+            if multiplexed && header_lines == 0 {
+                return Ok(SessionEnd::Closed);
+            }
             let command = command.ok_or_else(|| parse_error!("missing command"))?;
-            let _span = info_span!("command", command = format_args!("{:?}", command),).entered();
+            let command_span = info_span!(
+                "command",
+                command = format_args!("{:?}", command),
+                reason = field::Empty,
+                elapsed_us = field::Empty,
+            )
+            .entered();
+            let _timer = CommandTimer::start(&command_span, self.clock.clone());
 
             match command.as_str() {
                 t @ "clean" | t @ "smudge" => {
@@ -96,53 +612,162 @@ impl<P: Processor> GitFilterServer<P> {
                         _ => unreachable!(),
                     };
                     let pathname = pathname.ok_or_else(|| parse_error!("missing pathname"))?;
-                    let mut process_input = ReadPktUntilFlush::new(&mut input);
-                    if waiting_for_blobs {
+                    let mut process_input = ReadPktUntilFlush::new(&mut input)
+                        .with_max_size(self.max_input_size)
+                        .with_max_records(self.max_records_per_file);
+                    if !negotiated.supports(process_type) {
+                        // Protocol violation by client, processor never sees such file
+                        io::copy(&mut process_input, &mut io::sink())?;
+                        let e = anyhow::anyhow!("{} capability wasn't negotiated", t);
+                        self.file_failed(&command_span, &pathname, process_type, &e);
+                        output.pkt_text_write("status=error")?;
+                        output.pkt_end()?;
+                        continue;
+                    }
+                    // Git should only request delayed files after they were listed. File, which
+                    // was never delayed (or was delayed for other operation), is processed
+                    // right away, and delayed file requested before it was listed is failed
+                    let (resolving, premature) = match self.delayed.get(&pathname) {
+                        Some(file) if file.process_type == process_type => {
+                            (file.available, !file.available)
+                        }
+                        _ => (false, false),
+                    };
+                    if premature {
+                        io::copy(&mut process_input, &mut io::sink())?;
+                        self.delayed.remove(&pathname);
+                        self.processor.cancel_scheduled(&pathname, process_type);
+                        self.summary.files += 1;
+                        let e = anyhow::anyhow!("git requested delayed file before it was listed");
+                        self.file_failed(&command_span, &pathname, process_type, &e);
+                        output.pkt_text_write("status=error")?;
+                        output.pkt_end()?;
+                        continue;
+                    }
+                    if waiting_for_blobs && !resolving {
+                        warn!(
+                            pathname = format_args!("{}", pathname),
+                            "git requested file, which wasn't delayed, processing it now"
+                        );
+                    }
+                    if resolving {
                         let _span = info_span!(
                             "resolving delayed",
                             pathname = format_args!("{}", pathname)
                         )
                         .entered();
                         let mut sink = [0; 1];
-                        process_input
-                            .read_exact(&mut sink)
-                            .map_err(|_| parse_error!("delayed blob should have no data"))?;
+                        if process_input.read(&mut sink)? != 0 {
+                            return Err(parse_error!("delayed blob should have no data"));
+                        }
                         assert!(process_input.finished());
+                        let abandoned = self
+                            .delayed
+                            .remove(&pathname)
+                            .is_some_and(|file| file.abandoned);
+                        self.summary.files += 1;
+                        if abandoned {
+                            let e = anyhow::anyhow!("processor never produced delayed file");
+                            self.file_failed(&command_span, &pathname, process_type, &e);
+                            end_response(&mut output, false, false)?;
+                            continue;
+                        }
 
-                        output.pkt_text_write("status=success")?;
-                        output.pkt_end()?;
-                        let mut process_output = WritePkt::new(&mut output);
-                        if let Err(e) =
-                            self.0
-                                .get_scheduled(&pathname, process_type, &mut process_output)
-                        {
-                            process_output.flush()?;
-                            drop(process_output);
-                            error!("{:#}", e);
-                            output.pkt_end()?;
-                            output.pkt_text_write("status=error")?;
-                            output.pkt_end()?;
-                            return Ok(());
-                        } else {
-                            process_output.flush()?;
-                            drop(process_output);
-                            output.pkt_end()?;
-                            // Keep status
-                            output.pkt_end()?;
+                        let mut process_output = WritePkt::new(&mut output)
+                            .with_max_size(self.max_output_size)
+                            .with_record_size(self.fixed_record_size)
+                            .with_buffer_size(self.output_buffer_size)
+                            .with_hint_limit(self.hint_limit(&pathname, process_type))
+                            .with_deferred_status();
+                        let mut validated = self.validator_for(process_type);
+                        let mut panicked = false;
+                        let result =
+                            run_processor(
+                                self.catch_panics,
+                                &mut panicked,
+                                || match &mut validated {
+                                    Some((_, buffer)) => self.processor.get_scheduled(
+                                        &pathname,
+                                        process_type,
+                                        buffer,
+                                    ),
+                                    None => self.processor.get_scheduled(
+                                        &pathname,
+                                        process_type,
+                                        &mut process_output,
+                                    ),
+                                },
+                            );
+                        let result =
+                            result.and_then(|()| send_validated(validated, &mut process_output));
+                        process_output.finish()?;
+                        self.summary.bytes_out += process_output.written();
+                        let status_sent = process_output.status_sent();
+                        drop(process_output);
+                        end_response(&mut output, status_sent, result.is_ok())?;
+                        match result {
+                            Ok(()) => self.file_done(&pathname, process_type),
+                            Err(e) => {
+                                self.file_failed(&command_span, &pathname, process_type, &e);
+                                if !panicked {
+                                    return Ok(SessionEnd::Abandoned);
+                                }
+                            }
                         }
-                    } else if can_delay && self.0.should_delay(&pathname, process_type) {
+                    } else if !waiting_for_blobs
+                        && can_delay
+                        && !self.delayed.contains_key(&pathname)
+                        && self.processor.should_delay(&pathname, process_type)
+                    {
                         let _span =
                             info_span!("scheduling", pathname = format_args!("{}", pathname))
                                 .entered();
-                        if let Err(e) =
-                            self.0
-                                .schedule_process(&pathname, process_type, &mut process_input)
-                        {
-                            error!("{:#}", e);
+                        #[cfg(feature = "hashing")]
+                        let mut hashing_input =
+                            self.checksum_reader(&mut process_input, checksum.as_deref());
+                        #[cfg(feature = "hashing")]
+                        let checked_input = &mut hashing_input;
+                        #[cfg(not(feature = "hashing"))]
+                        let checked_input = &mut process_input;
+                        let mut panicked = false;
+                        let result = run_processor(self.catch_panics, &mut panicked, || {
+                            self.processor.schedule_process(
+                                &pathname,
+                                process_type,
+                                &mut *checked_input,
+                            )
+                        });
+                        if panicked {
+                            io::copy(checked_input, &mut io::sink())?;
+                        } else if result.is_ok() {
+                            self.drain_unread(checked_input)?;
+                        }
+                        let scheduled = result.is_ok();
+                        #[cfg(feature = "hashing")]
+                        let result =
+                            result.and_then(|()| check_digest(hashing_input, checksum.as_deref()));
+                        self.summary.bytes_in += process_input.read_bytes();
+                        let result = result.and_then(|()| check_terminated(&process_input));
+                        if let Err(e) = result {
+                            if scheduled {
+                                // Git won't ask for file, which it got error for
+                                self.processor.cancel_scheduled(&pathname, process_type);
+                            }
+                            self.file_failed(&command_span, &pathname, process_type, &e);
                             output.pkt_text_write("status=error")?;
                             output.pkt_end()?;
-                            return Ok(());
+                            if !panicked || !process_input.terminated_cleanly() {
+                                return Ok(SessionEnd::Abandoned);
+                            }
                         } else {
+                            self.delayed.insert(
+                                pathname.clone(),
+                                DelayedFile {
+                                    process_type,
+                                    available: false,
+                                    abandoned: false,
+                                },
+                            );
                             output.pkt_text_write("status=delayed")?;
                             output.pkt_end()?;
                         }
@@ -150,48 +775,318 @@ impl<P: Processor> GitFilterServer<P> {
                         let _span =
                             info_span!("processing", pathname = format_args!("{}", pathname))
                                 .entered();
-                        output.pkt_text_write("status=success")?;
-                        output.pkt_end()?;
-                        let mut process_output = WritePkt::new(&mut output);
-                        if let Err(e) = self.0.process(
-                            &pathname,
-                            process_type,
-                            &mut process_input,
-                            &mut process_output,
-                        ) {
-                            process_output.flush()?;
-                            drop(process_output);
-                            error!("{:#}", e);
-                            output.pkt_end()?;
-                            output.pkt_text_write("status=error")?;
-                            output.pkt_end()?;
-                            return Ok(());
-                        } else {
-                            process_output.flush()?;
-                            drop(process_output);
-                            output.pkt_end()?;
-                            // Keep status
-                            output.pkt_end()?;
+                        let mut process_output = WritePkt::new(&mut output)
+                            .with_max_size(self.max_output_size)
+                            .with_record_size(self.fixed_record_size)
+                            .with_buffer_size(self.output_buffer_size)
+                            .with_hint_limit(self.hint_limit(&pathname, process_type))
+                            .with_deferred_status();
+                        #[cfg(feature = "hashing")]
+                        let mut hashing_input =
+                            self.checksum_reader(&mut process_input, checksum.as_deref());
+                        #[cfg(feature = "hashing")]
+                        let checked_input = &mut hashing_input;
+                        #[cfg(not(feature = "hashing"))]
+                        let checked_input = &mut process_input;
+                        let mut validated = self.validator_for(process_type);
+                        let mut panicked = false;
+                        let result =
+                            run_processor(
+                                self.catch_panics,
+                                &mut panicked,
+                                || match &mut validated {
+                                    Some((_, buffer)) => self.processor.process(
+                                        &pathname,
+                                        process_type,
+                                        &mut *checked_input,
+                                        buffer,
+                                    ),
+                                    None => self.processor.process(
+                                        &pathname,
+                                        process_type,
+                                        &mut *checked_input,
+                                        &mut process_output,
+                                    ),
+                                },
+                            );
+                        if panicked {
+                            io::copy(checked_input, &mut io::sink())?;
+                        } else if result.is_ok() {
+                            self.drain_unread(checked_input)?;
+                        }
+                        #[cfg(feature = "hashing")]
+                        let result =
+                            result.and_then(|()| check_digest(hashing_input, checksum.as_deref()));
+                        let result = result.and_then(|()| check_terminated(&process_input));
+                        let result =
+                            result.and_then(|()| send_validated(validated, &mut process_output));
+                        process_output.finish()?;
+                        self.summary.files += 1;
+                        self.summary.bytes_in += process_input.read_bytes();
+                        self.summary.bytes_out += process_output.written();
+                        let status_sent = process_output.status_sent();
+                        drop(process_output);
+                        end_response(&mut output, status_sent, result.is_ok())?;
+                        match result {
+                            Ok(()) => self.file_done(&pathname, process_type),
+                            Err(e) => {
+                                self.file_failed(&command_span, &pathname, process_type, &e);
+                                if !panicked || !process_input.terminated_cleanly() {
+                                    return Ok(SessionEnd::Abandoned);
+                                }
+                            }
                         }
                     }
                     // Input should be stopped at flush
                     assert!(process_input.finished());
                 }
                 "list_available_blobs" => {
-                    self.0.switch_to_wait();
-                    waiting_for_blobs = true;
+                    if !waiting_for_blobs {
+                        self.processor.switch_to_wait();
+                        waiting_for_blobs = true;
+                    }
+                    match self.collect_available() {
+                        Ok(available) => write_available_blobs(&mut output, &available)?,
+                        Err(e) => {
+                            error!("failed to list available blobs: {:#}", e);
+                            output.pkt_end()?;
+                            output.pkt_text_write("status=error")?;
+                            output.pkt_end()?;
+                            return Ok(SessionEnd::Abandoned);
+                        }
+                    }
                 }
                 cmd => return Err(parse_error!(format!("unknown command: {}", cmd))),
             }
         }
     }
 
-    pub fn communicate<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<()> {
-        match self.communicate_internal(input, output) {
-            Ok(_) => Ok(()),
+    /// Consume input, which processor left unread after success
+    fn drain_unread<R: Read>(&self, input: &mut R) -> Result<()> {
+        let unread = io::copy(input, &mut io::sink())?;
+        if unread != 0 {
+            match self.unread_input {
+                UnreadInput::Drain => {
+                    warn!(
+                        unread,
+                        "processor didn't consume whole input, discarding the rest"
+                    )
+                }
+                UnreadInput::Panic => {
+                    panic!(
+                        "processor didn't consume whole input, {} bytes left",
+                        unread
+                    )
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Output size limit derived from processor size hint, in strict mode
+    fn hint_limit(&self, pathname: &Pathname, process_type: ProcessingType) -> Option<u64> {
+        let tolerance = self.size_hint_tolerance?;
+        let hint = self.processor.output_size_hint(pathname, process_type)?;
+        Some(hint.saturating_add(tolerance))
+    }
+
+    /// Input reader, computing digest for `checksum=` header, if verification is enabled
+    #[cfg(feature = "hashing")]
+    fn checksum_reader<R: Read>(&self, input: R, checksum: Option<&str>) -> MultiHashingReader<R> {
+        let reader = MultiHashingReader::new(input);
+        match (self.checksum_digest, checksum) {
+            (Some(digest), Some(_)) => reader.with_boxed_digest(digest()),
+            _ => reader,
+        }
+    }
+
+    /// Validator for processing type, with buffer for output it checks
+    fn validator_for(
+        &self,
+        process_type: ProcessingType,
+    ) -> Option<(OutputValidator, BoundedBuffer)> {
+        self.output_validators
+            .get(&process_type)
+            .map(|validator| (validator.clone(), BoundedBuffer::new(self.max_output_size)))
+    }
+
+    /// Poll processor for delayed files, until batch is ready, or no pending files remain
+    fn collect_available(&mut self) -> anyhow::Result<Vec<Pathname>> {
+        // Git may ask even if nothing was delayed, processor might not implement delaying at all
+        if self.delayed.values().all(|f| f.available) {
+            return Ok(Vec::new());
+        }
+        let mut available = Vec::new();
+        let mut empty_polls = 0;
+        loop {
+            let ready = self.processor.get_available()?;
+            let listed = available.len();
+            for pathname in ready {
+                match self.delayed.get_mut(&pathname) {
+                    Some(file) if !file.available => {
+                        file.available = true;
+                        available.push(pathname);
+                    }
+                    _ => warn!(
+                        pathname = format_args!("{}", pathname),
+                        "processor reported blob, which is not pending"
+                    ),
+                }
+            }
+            let pending = self.delayed.values().filter(|f| !f.available).count();
+            if pending == 0 || available.len() >= self.available_batch_size {
+                return Ok(available);
+            }
+            // Bogus pathnames are no progress, or processor repeating them would spin forever
+            if available.len() > listed {
+                empty_polls = 0;
+                continue;
+            }
+            empty_polls += 1;
+            if empty_polls >= self.max_empty_polls {
+                warn!(
+                    pending,
+                    "processor produced no delayed files, giving up on them"
+                );
+                for (pathname, file) in self.delayed.iter_mut().filter(|(_, f)| !f.available) {
+                    file.available = true;
+                    file.abandoned = true;
+                    available.push(pathname.clone());
+                }
+                return Ok(available);
+            }
+            self.clock.sleep(self.available_poll_interval);
+        }
+    }
+
+    /// Collect metadata of successfully processed file to summary
+    fn file_done(&mut self, pathname: &Pathname, process_type: ProcessingType) {
+        let metadata = self.processor.file_metadata(pathname, process_type);
+        if let Some(metadata) = metadata {
+            self.summary.metadata.push(FileMetadataRecord {
+                pathname: pathname.clone(),
+                process_type,
+                metadata,
+            });
+        }
+    }
+
+    /// Cancel every delayed file, which wasn't delivered to git yet
+    ///
+    /// Calls [`Processor::cancel_scheduled`] for each of them, so background work is
+    /// torn down. Done once session ends, returns count of cancelled files
+    fn cancel_delayed(&mut self) -> usize {
+        let delayed = std::mem::take(&mut self.delayed);
+        let cancelled = delayed.len();
+        if cancelled != 0 {
+            info!(cancelled, "cancelling delayed files");
+        }
+        for (pathname, file) in delayed {
+            self.processor
+                .cancel_scheduled(&pathname, file.process_type);
+        }
+        cancelled
+    }
+
+    /// Report file processing failure to logs and summary
+    ///
+    /// Reason is never sent to git, as protocol only permits bare `status=error`,
+    /// see [`GitFilterServer::report_errors_to_user`]
+    fn file_failed(
+        &mut self,
+        span: &Span,
+        pathname: &Pathname,
+        process_type: ProcessingType,
+        e: &anyhow::Error,
+    ) {
+        let reason = format!("{:#}", e);
+        span.record("reason", &reason.as_str());
+        error!("{}", reason);
+        if self.report_errors_to_user {
+            let name = self.processor.name();
+            let filter = if name.is_empty() { "filter" } else { name };
+            warn_user(&format!(
+                "{}: {} of {} failed: {}",
+                filter,
+                process_type.acc_name(),
+                pathname,
+                reason
+            ));
+        }
+        self.summary.errors.push(FileError {
+            pathname: pathname.clone(),
+            process_type,
+            reason,
+        });
+    }
+
+    fn session<R: Read, W: Write>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+        multiplexed: bool,
+    ) -> Result<SessionEnd> {
+        let _span = info_span!("session", filter = self.processor.name()).entered();
+        #[cfg(feature = "alloc-stats")]
+        let allocations = AllocationStats::current();
+        let started = self.clock.now();
+        let result = self.communicate_internal(input, output, multiplexed);
+        // Client disconnected or session failed with files still scheduled
+        self.cancel_delayed();
+        self.summary.duration = self.clock.now().saturating_duration_since(started);
+        #[cfg(feature = "alloc-stats")]
+        {
+            self.summary.allocations = AllocationStats::current().since(&allocations);
+        }
+        if let Some(sink) = &mut self.metrics_sink {
+            sink.record(&self.summary);
+        }
+        match result {
             // Communication is done, not a error
-            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(()),
-            Err(e) => Err(e),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(SessionEnd::Eof),
+            result => result,
+        }
+    }
+
+    pub fn communicate<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<()> {
+        self.session(input, output, false)?;
+        Ok(())
+    }
+
+    /// Serve consecutive sessions over single stream, each starting with its own handshake
+    ///
+    /// Git itself never does this, protocol has no end of session besides closing the stream.
+    /// For transports, which keep connection open, client ends session by sending flush in
+    /// place of next command (empty command list), and the next session starts right after it.
+    /// Server reads exactly one session worth of bytes, so no data is lost between sessions.
+    ///
+    /// Returns count of served sessions, once stream is closed. Closing stream in place of
+    /// the next hello doesn't start a session, so processor hooks and metrics sink only see
+    /// real ones, while the last session may also end by closing stream, as with git.
+    /// If session is abandoned after failed file, stream can't be resynchronized,
+    /// and error is returned. [`GitFilterServer::summary`] only covers the last session
+    pub fn communicate_multiplexed<R: Read, W: Write>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<usize> {
+        let mut sessions = 0;
+        loop {
+            let mut first = [0; 1];
+            if retry_interrupted(|| input.read(&mut first))? == 0 {
+                return Ok(sessions);
+            }
+            sessions += 1;
+            let mut input = (&first[..]).chain(&mut *input);
+            match self.session(&mut input, output, true)? {
+                SessionEnd::Closed => {}
+                SessionEnd::Eof => return Ok(sessions),
+                SessionEnd::Abandoned => {
+                    return Err(io::Error::other(
+                        "session was abandoned, stream can't be resynchronized",
+                    ))
+                }
+            }
         }
     }
 
@@ -199,7 +1094,60 @@ impl<P: Processor> GitFilterServer<P> {
         let stdin = std::io::stdin();
         let stdout = std::io::stdout();
 
-        self.communicate(&mut stdin.lock(), &mut stdout.lock())?;
+        if let Some(timeout) = self.write_timeout {
+            let mut output = TimeoutWriter::new(stdout, timeout)?;
+            self.communicate(&mut stdin.lock(), &mut output)?;
+        } else {
+            self.communicate(&mut stdin.lock(), &mut stdout.lock())?;
+        }
         Ok(())
     }
+
+    /// Run server over stdio, intended to be returned from `main` of filter binary
+    ///
+    /// Logs are written to stderr, since stdout is occupied by protocol,
+    /// and filtered by `RUST_LOG` variable. With `json` feature, `--describe`
+    /// argument prints [`Description`] instead of serving git.
+    ///
+    /// Exits with 0 once git closes the stream, and with 1 on protocol errors
+    /// or failed [`Processor::start_session`].
+    /// Git never acts on exit status of long-running filter, exit code is only
+    /// for wrappers and manual runs. What git reacts to is the stream closing
+    /// before it is done with filter: current file fails, which is fatal if
+    /// `filter.<driver>.required` is set, and filter isn't restarted for remaining files.
+    #[cfg(feature = "main")]
+    pub fn run_stdio_main(mut self) -> std::process::ExitCode {
+        use std::process::ExitCode;
+        let _ = tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+            .try_init();
+
+        #[cfg(feature = "json")]
+        if std::env::args().skip(1).any(|arg| arg == "--describe") {
+            return match self.describe_stdio() {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    error!("failed to write description: {}", e);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+
+        #[cfg(all(feature = "rlimit", unix))]
+        if let Some(limits) = &self.resource_limits {
+            if let Err(e) = limits.apply() {
+                error!("failed to apply resource limits: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+
+        match self.communicate_stdio() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("session failed: {}", e);
+                ExitCode::FAILURE
+            }
+        }
+    }
 }
@@ -0,0 +1,150 @@
+use std::{
+    io::{BufReader, BufWriter, Error, ErrorKind, Result},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use tracing::{info_span, warn};
+
+use crate::{GitFilterServer, Processor};
+
+/// Behavior for connections accepted while all session slots are taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtCapacity {
+    /// Hold connection until some session ends, further connections wait in listen backlog
+    Queue,
+    /// Close connection right after accepting it
+    Reject,
+}
+
+/// Delay before next accept after failed one, or `None` if listener can't recover
+///
+/// Aborted connections only affect that connection, while running out of descriptors or
+/// buffers is temporary under load, and retrying it right away would spin. Descriptor
+/// exhaustion has no [`ErrorKind`], so it is only recognized on unix
+fn accept_backoff(e: &Error) -> Option<Duration> {
+    match e.kind() {
+        ErrorKind::ConnectionAborted
+        | ErrorKind::ConnectionReset
+        | ErrorKind::Interrupted
+        | ErrorKind::TimedOut => return Some(Duration::ZERO),
+        // Nonblocking listener has nothing to accept yet
+        ErrorKind::WouldBlock => return Some(Duration::from_millis(10)),
+        ErrorKind::OutOfMemory => return Some(Duration::from_millis(100)),
+        _ => {}
+    }
+    #[cfg(unix)]
+    if let Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM) = e.raw_os_error() {
+        return Some(Duration::from_millis(100));
+    }
+    None
+}
+
+struct Slots {
+    used: Mutex<usize>,
+    freed: Condvar,
+}
+
+struct SlotGuard(Arc<Slots>);
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        *self.0.used.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// Serves filter sessions over TCP, each connection runs one session on its own thread
+///
+/// Server for every session is created by factory, so processor doesn't need to be `Send`
+pub struct TcpFilterServer<F> {
+    factory: Arc<F>,
+    max_sessions: usize,
+    at_capacity: AtCapacity,
+}
+
+impl<F> TcpFilterServer<F> {
+    pub fn new(factory: F) -> Self {
+        Self {
+            factory: Arc::new(factory),
+            max_sessions: 16,
+            at_capacity: AtCapacity::Queue,
+        }
+    }
+
+    /// Limit count of concurrently running sessions, default is 16
+    pub fn max_sessions(mut self, max_sessions: usize) -> Self {
+        self.max_sessions = max_sessions.max(1);
+        self
+    }
+
+    /// What to do with connections over the limit, default is [`AtCapacity::Queue`]
+    pub fn at_capacity(mut self, at_capacity: AtCapacity) -> Self {
+        self.at_capacity = at_capacity;
+        self
+    }
+}
+
+impl<F, P> TcpFilterServer<F>
+where
+    F: Fn() -> GitFilterServer<P> + Send + Sync + 'static,
+    P: Processor,
+{
+    /// Accept connections until listener fails
+    ///
+    /// Temporary accept errors, such as aborted connection or descriptor exhaustion,
+    /// are logged and retried
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        let slots = Arc::new(Slots {
+            used: Mutex::new(0),
+            freed: Condvar::new(),
+        });
+        loop {
+            let (stream, peer) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) => match accept_backoff(&e) {
+                    Some(backoff) => {
+                        warn!("accept failed, retrying: {}", e);
+                        thread::sleep(backoff);
+                        continue;
+                    }
+                    None => return Err(e),
+                },
+            };
+            {
+                let mut used = slots.used.lock().unwrap();
+                if *used >= self.max_sessions {
+                    match self.at_capacity {
+                        AtCapacity::Reject => {
+                            warn!(peer = %peer, "rejecting connection, session limit reached");
+                            continue;
+                        }
+                        AtCapacity::Queue => {
+                            while *used >= self.max_sessions {
+                                used = slots.freed.wait(used).unwrap();
+                            }
+                        }
+                    }
+                }
+                *used += 1;
+            }
+            let guard = SlotGuard(slots.clone());
+            let factory = self.factory.clone();
+            thread::spawn(move || {
+                let _guard = guard;
+                let _span = info_span!("connection", peer = %peer).entered();
+                if let Err(e) = serve_connection(factory(), stream) {
+                    warn!("session failed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+fn serve_connection<P: Processor>(mut server: GitFilterServer<P>, stream: TcpStream) -> Result<()> {
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut output = BufWriter::new(stream);
+    server.communicate(&mut input, &mut output)
+}
@@ -0,0 +1,75 @@
+use std::{borrow::Cow, ffi::OsString, fmt, path::PathBuf};
+
+/// Pathname of file, exactly as sent by git
+///
+/// Git doesn't require pathnames to be valid UTF-8, so they are kept as raw bytes
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pathname {
+    bytes: Vec<u8>,
+}
+
+impl Pathname {
+    pub fn from_bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        Self {
+            bytes: bytes.into(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Pathname, if it is valid UTF-8
+    pub fn to_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.bytes).ok()
+    }
+
+    /// Pathname with invalid UTF-8 sequences replaced, for logs and messages
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.bytes)
+    }
+
+    /// Pathname in platform encoding, for use with filesystem
+    ///
+    /// On unix bytes are used as is. Git for Windows always sends UTF-8,
+    /// so elsewhere invalid sequences are replaced
+    pub fn to_os_string(&self) -> OsString {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            OsString::from_vec(self.bytes.clone())
+        }
+        #[cfg(not(unix))]
+        {
+            OsString::from(self.to_string_lossy().into_owned())
+        }
+    }
+
+    pub fn to_path_buf(&self) -> PathBuf {
+        PathBuf::from(self.to_os_string())
+    }
+}
+
+impl fmt::Display for Pathname {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_lossy())
+    }
+}
+
+impl AsRef<[u8]> for Pathname {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl From<&str> for Pathname {
+    fn from(pathname: &str) -> Self {
+        Self::from_bytes(pathname)
+    }
+}
+
+impl From<String> for Pathname {
+    fn from(pathname: String) -> Self {
+        Self::from_bytes(pathname)
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{Pathname, ProcessingType, Processor};
+use anyhow::{Context, Result};
+use std::io::{Read, Write};
+
+/// Single stage of [`Pipeline`]
+pub trait Transform {
+    /// Transform whole input to output
+    ///
+    /// Smudge is expected to revert, what clean did
+    fn apply(
+        &mut self,
+        process_type: ProcessingType,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<()>;
+}
+
+/// Processor, which passes data through chain of transforms,
+/// in order for clean, and in reverse order for smudge
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Transform + Send>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append stage to the end of clean chain (and to the start of smudge chain)
+    pub fn stage(mut self, stage: impl Transform + Send + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+}
+
+impl Processor for Pipeline {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        _pathname: &Pathname,
+        process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+
+        let mut run = |i: usize, stage: &mut Box<dyn Transform + Send>| -> Result<()> {
+            let mut stage_output = Vec::new();
+            stage
+                .apply(process_type, &mut data.as_slice(), &mut stage_output)
+                .with_context(|| format!("pipeline stage {} failed", i))?;
+            data = stage_output;
+            Ok(())
+        };
+        match process_type {
+            ProcessingType::Clean => {
+                for (i, stage) in self.stages.iter_mut().enumerate() {
+                    run(i, stage)?;
+                }
+            }
+            ProcessingType::Smudge => {
+                for (i, stage) in self.stages.iter_mut().enumerate().rev() {
+                    run(i, stage)?;
+                }
+            }
+        }
+
+        output.write_all(&data)?;
+        Ok(())
+    }
+
+    fn supports_processing(&self, _process_type: ProcessingType) -> bool {
+        true
+    }
+}
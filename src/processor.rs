@@ -1,8 +1,8 @@
-use crate::parse_error;
+use crate::{parse_error, FileMetadata, Pathname};
 use anyhow::Result;
 use std::io::{Read, Write};
 
-#[derive(PartialEq, Clone, Copy, Hash)]
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
 pub enum ProcessingType {
     /// Clean filter is ran on stage
     Clean,
@@ -11,6 +11,8 @@ pub enum ProcessingType {
 }
 
 impl ProcessingType {
+    pub const ALL: [ProcessingType; 2] = [ProcessingType::Clean, ProcessingType::Smudge];
+
     pub fn name(&self) -> &'static str {
         match self {
             ProcessingType::Clean => "clean",
@@ -34,14 +36,31 @@ impl ProcessingType {
 /// This trait is used for user-defined logic of git-filter-server
 /// Typically git talks with processor via stdio, so better do not use it inside
 pub trait Processor {
+    /// Validate environment, before handshake with git is performed
+    ///
+    /// Failure terminates session before any file is processed. Git then reports
+    /// "initialization for subprocess failed", and fails every file using this filter,
+    /// which aborts the operation if `filter.<driver>.required` is set
+    fn start_session(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     /// Handle clean/smudge operation
     ///
+    /// Pathname is passed exactly as sent by git, which doesn't require it to be valid UTF-8,
+    /// use [`Pathname::to_str`] or [`Pathname::to_path_buf`] to access it
+    ///
     /// Warning:
     /// Git doesn't support streaming, you should read input, and then write output,
     /// not to pipe input via handler to output
+    ///
+    /// Output itself is streamed: data is sent to git in pkt records as soon as record
+    /// is filled (or output is flushed), so large outputs don't need to be kept in memory.
+    /// There is no way to send partial result and continue, protocol has no
+    /// checkpoints inside of content, and the first flush packet is treated as end of it
     fn process<R: Read, W: Write>(
         &mut self,
-        _pathname: &str,
+        _pathname: &Pathname,
         _process_type: ProcessingType,
         _input: &mut R,
         _output: &mut W,
@@ -52,7 +71,7 @@ pub trait Processor {
     /// Schedule delayed execution
     fn schedule_process<R: Read>(
         &mut self,
-        _pathname: &str,
+        _pathname: &Pathname,
         _process_type: ProcessingType,
         _input: &mut R,
     ) -> Result<()> {
@@ -62,26 +81,65 @@ pub trait Processor {
     /// Get data for file, previously scheduled via schedule_process
     fn get_scheduled<W: Write>(
         &mut self,
-        _pathname: &str,
+        _pathname: &Pathname,
         _process_type: ProcessingType,
         _output: &mut W,
     ) -> Result<()> {
         panic!("delayed processing is not implemented")
     }
+    /// Abort file, previously scheduled via schedule_process, which won't be requested anymore
+    ///
+    /// Called when session ends before git requested it, i.e after disconnect or failed file,
+    /// and when git requested it before it was listed as available
+    fn cancel_scheduled(&mut self, _pathname: &Pathname, _process_type: ProcessingType) {}
+
     /// Called once all files are already scheduled/processed
     fn switch_to_wait(&mut self) {}
 
     /// Get scheduled files ready for outputting
-    fn get_available(&mut self) -> Result<Vec<String>> {
+    ///
+    /// May return empty list if nothing is ready yet, in this case it will be polled again,
+    /// until every scheduled file is reported, see [`crate::GitFilterServer::available_batch_size`]
+    fn get_available(&mut self) -> Result<Vec<Pathname>> {
         panic!("delayed processing is not implemented")
     }
 
     /// Should processing of file be delayed?
     /// Only use it for long-running tasks, i.e file downloading, which would be better parallelized
-    fn should_delay(&self, _pathname: &str, _process_type: ProcessingType) -> bool {
+    fn should_delay(&self, _pathname: &Pathname, _process_type: ProcessingType) -> bool {
+        false
+    }
+
+    /// Expected size of output for file, advisory unless server is configured with
+    /// [`crate::GitFilterServer::strict_size_hint`]
+    fn output_size_hint(&self, _pathname: &Pathname, _process_type: ProcessingType) -> Option<u64> {
+        None
+    }
+
+    /// Is repeating processing of the same input safe?
+    ///
+    /// Advisory hint for supervisors, deciding whether failed session can be retried,
+    /// exposed via [`crate::SessionSummary::idempotent`]. Server behavior is not affected
+    fn is_idempotent(&self, _process_type: ProcessingType) -> bool {
         false
     }
 
+    /// Metadata of file, which was just successfully processed or got from schedule
+    ///
+    /// Collected to [`crate::SessionSummary::metadata`] for embedder, and is never sent to git
+    fn file_metadata(
+        &mut self,
+        _pathname: &Pathname,
+        _process_type: ProcessingType,
+    ) -> Option<FileMetadata> {
+        None
+    }
+
+    /// Name of filter, attached to session span to distinguish filters in logs
+    fn name(&self) -> &str {
+        ""
+    }
+
     /// Does this filter supports clean/smudge?
     fn supports_processing(&self, _process_type: ProcessingType) -> bool {
         false
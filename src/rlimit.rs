@@ -0,0 +1,61 @@
+use std::{
+    convert::TryFrom,
+    io::{Error, ErrorKind, Result},
+    time::Duration,
+};
+
+/// Limits of resources, which filter process is allowed to use,
+/// see [`crate::GitFilterServer::resource_limits`]
+///
+/// Applied to the whole process, via setrlimit. Both soft and hard limits are set,
+/// so limits can't be raised back. Exceeding memory limit fails allocation (which
+/// aborts process), exceeding CPU time kills process with SIGXCPU, git then fails
+/// file being processed. For limits on a group of processes use cgroups instead.
+///
+/// Only available on unix
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Max size of address space in bytes
+    pub max_memory: Option<u64>,
+    /// Max CPU time, rounded up to whole seconds
+    pub max_cpu_time: Option<Duration>,
+}
+
+impl ResourceLimits {
+    /// Apply limits to current process
+    pub fn apply(&self) -> Result<()> {
+        if let Some(memory) = self.max_memory {
+            set_limit(Resource::Memory, memory)?;
+        }
+        if let Some(cpu) = self.max_cpu_time {
+            let seconds = cpu.as_secs() + u64::from(cpu.subsec_nanos() != 0);
+            set_limit(Resource::Cpu, seconds.max(1))?;
+        }
+        Ok(())
+    }
+}
+
+enum Resource {
+    Memory,
+    Cpu,
+}
+
+fn set_limit(resource: Resource, value: u64) -> Result<()> {
+    let value = libc::rlim_t::try_from(value)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "resource limit is too large"))?;
+    let limit = libc::rlimit {
+        rlim_cur: value,
+        rlim_max: value,
+    };
+    // SAFETY: pointer is valid for duration of call
+    let result = unsafe {
+        match resource {
+            Resource::Memory => libc::setrlimit(libc::RLIMIT_AS, &limit),
+            Resource::Cpu => libc::setrlimit(libc::RLIMIT_CPU, &limit),
+        }
+    };
+    if result != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
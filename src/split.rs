@@ -0,0 +1,138 @@
+use crate::{FileMetadata, Pathname, ProcessingType, Processor};
+use anyhow::Result;
+use std::io::{Read, Write};
+
+/// Processor, which routes clean and smudge to different inner processors
+///
+/// Clean is advertised only if `clean` supports it, and smudge only if `smudge` supports it
+pub struct SplitProcessor<C, S> {
+    pub clean: C,
+    pub smudge: S,
+    clean_delayed: bool,
+    smudge_delayed: bool,
+}
+
+impl<C, S> SplitProcessor<C, S> {
+    pub fn new(clean: C, smudge: S) -> Self {
+        Self {
+            clean,
+            smudge,
+            clean_delayed: false,
+            smudge_delayed: false,
+        }
+    }
+}
+
+impl<C: Processor, S: Processor> Processor for SplitProcessor<C, S> {
+    fn start_session(&mut self) -> Result<()> {
+        self.clean_delayed = false;
+        self.smudge_delayed = false;
+        self.clean.start_session()?;
+        self.smudge.start_session()
+    }
+
+    fn process<R: Read, W: Write>(
+        &mut self,
+        pathname: &Pathname,
+        process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        match process_type {
+            ProcessingType::Clean => self.clean.process(pathname, process_type, input, output),
+            ProcessingType::Smudge => self.smudge.process(pathname, process_type, input, output),
+        }
+    }
+
+    fn schedule_process<R: Read>(
+        &mut self,
+        pathname: &Pathname,
+        process_type: ProcessingType,
+        input: &mut R,
+    ) -> Result<()> {
+        match process_type {
+            ProcessingType::Clean => {
+                self.clean_delayed = true;
+                self.clean.schedule_process(pathname, process_type, input)
+            }
+            ProcessingType::Smudge => {
+                self.smudge_delayed = true;
+                self.smudge.schedule_process(pathname, process_type, input)
+            }
+        }
+    }
+
+    fn get_scheduled<W: Write>(
+        &mut self,
+        pathname: &Pathname,
+        process_type: ProcessingType,
+        output: &mut W,
+    ) -> Result<()> {
+        match process_type {
+            ProcessingType::Clean => self.clean.get_scheduled(pathname, process_type, output),
+            ProcessingType::Smudge => self.smudge.get_scheduled(pathname, process_type, output),
+        }
+    }
+
+    fn cancel_scheduled(&mut self, pathname: &Pathname, process_type: ProcessingType) {
+        match process_type {
+            ProcessingType::Clean => self.clean.cancel_scheduled(pathname, process_type),
+            ProcessingType::Smudge => self.smudge.cancel_scheduled(pathname, process_type),
+        }
+    }
+
+    fn switch_to_wait(&mut self) {
+        self.clean.switch_to_wait();
+        self.smudge.switch_to_wait();
+    }
+
+    fn get_available(&mut self) -> Result<Vec<Pathname>> {
+        let mut available = Vec::new();
+        if self.clean_delayed {
+            available.extend(self.clean.get_available()?);
+        }
+        if self.smudge_delayed {
+            available.extend(self.smudge.get_available()?);
+        }
+        Ok(available)
+    }
+
+    fn should_delay(&self, pathname: &Pathname, process_type: ProcessingType) -> bool {
+        match process_type {
+            ProcessingType::Clean => self.clean.should_delay(pathname, process_type),
+            ProcessingType::Smudge => self.smudge.should_delay(pathname, process_type),
+        }
+    }
+
+    fn file_metadata(
+        &mut self,
+        pathname: &Pathname,
+        process_type: ProcessingType,
+    ) -> Option<FileMetadata> {
+        match process_type {
+            ProcessingType::Clean => self.clean.file_metadata(pathname, process_type),
+            ProcessingType::Smudge => self.smudge.file_metadata(pathname, process_type),
+        }
+    }
+
+    fn output_size_hint(&self, pathname: &Pathname, process_type: ProcessingType) -> Option<u64> {
+        match process_type {
+            ProcessingType::Clean => self.clean.output_size_hint(pathname, process_type),
+            ProcessingType::Smudge => self.smudge.output_size_hint(pathname, process_type),
+        }
+    }
+
+    fn is_idempotent(&self, process_type: ProcessingType) -> bool {
+        match process_type {
+            ProcessingType::Clean => self.clean.is_idempotent(process_type),
+            ProcessingType::Smudge => self.smudge.is_idempotent(process_type),
+        }
+    }
+
+    fn supports_processing(&self, process_type: ProcessingType) -> bool {
+        match process_type {
+            ProcessingType::Clean => self.clean.supports_processing(process_type),
+            ProcessingType::Smudge => self.smudge.supports_processing(process_type),
+        }
+    }
+}
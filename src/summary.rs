@@ -0,0 +1,56 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{Pathname, ProcessingType};
+
+/// Information collected during last session, see [`crate::GitFilterServer::summary`]
+#[derive(Debug, Clone, Default)]
+pub struct SessionSummary {
+    /// Capabilities offered by git, which are unknown to this server
+    pub unknown_capabilities: Vec<String>,
+    /// Files, for which `status=error` was reported to git
+    pub errors: Vec<FileError>,
+    /// Supported processing types, which processor declared idempotent
+    pub idempotent: Vec<ProcessingType>,
+    /// Files processed right away or delivered after delay, including failed ones
+    pub files: u64,
+    /// Content bytes received from git
+    pub bytes_in: u64,
+    /// Content bytes sent to git
+    pub bytes_out: u64,
+    /// Time from start of session to its end
+    pub duration: Duration,
+    /// Metadata of successfully processed files, see [`crate::Processor::file_metadata`]
+    pub metadata: Vec<FileMetadataRecord>,
+    /// Allocations made on session thread, counted by [`crate::CountingAllocator`]
+    #[cfg(feature = "alloc-stats")]
+    pub allocations: crate::AllocationStats,
+}
+
+/// Failure of single file processing
+#[derive(Debug, Clone)]
+pub struct FileError {
+    pub pathname: Pathname,
+    pub process_type: ProcessingType,
+    /// Formatted error chain, as returned by processor
+    pub reason: String,
+}
+
+/// Side-channel data about processed file, i.e detected MIME type or size
+///
+/// Only returned to embedder, git never sees it
+pub type FileMetadata = BTreeMap<String, String>;
+
+/// Metadata, reported by processor for single file
+#[derive(Debug, Clone)]
+pub struct FileMetadataRecord {
+    pub pathname: Pathname,
+    pub process_type: ProcessingType,
+    pub metadata: FileMetadata,
+}
+
+/// Receiver of aggregated session metrics, i.e exporter to Prometheus or statsd,
+/// see [`crate::GitFilterServer::metrics_sink`]
+pub trait MetricsSink: Send {
+    /// Called once at the end of every session, whether it failed or not
+    fn record(&mut self, summary: &SessionSummary);
+}
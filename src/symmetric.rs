@@ -0,0 +1,53 @@
+use crate::{Pathname, ProcessingType, Processor};
+use anyhow::Result;
+use std::io::{Read, Write};
+
+/// Filter, which is a single reversible transform
+///
+/// Clean runs [`SymmetricProcessor::forward`], smudge runs [`SymmetricProcessor::backward`],
+/// which is expected to restore original content, see `testing::assert_inverse`
+pub trait SymmetricProcessor {
+    /// Transform applied on clean
+    fn forward(
+        &mut self,
+        pathname: &Pathname,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<()>;
+
+    /// Inverse of forward, applied on smudge
+    fn backward(
+        &mut self,
+        pathname: &Pathname,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<()>;
+
+    /// Name of filter, see [`Processor::name`]
+    fn name(&self) -> &str {
+        ""
+    }
+}
+
+impl<T: SymmetricProcessor> Processor for T {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        pathname: &Pathname,
+        process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        match process_type {
+            ProcessingType::Clean => self.forward(pathname, input, output),
+            ProcessingType::Smudge => self.backward(pathname, input, output),
+        }
+    }
+
+    fn name(&self) -> &str {
+        SymmetricProcessor::name(self)
+    }
+
+    fn supports_processing(&self, _process_type: ProcessingType) -> bool {
+        true
+    }
+}
@@ -0,0 +1,659 @@
+//! Helpers for testing processors and wire behavior of the server
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write as _,
+    fs,
+    io::{self, ErrorKind, Read, Result, Write},
+    path::Path,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    ext::{ReadExt, WriteExt, MAX_PKT_SIZE},
+    parse_error, read_available_blobs, Capabilities, ClientHandshake, Clock, GitFilterServer,
+    Pathname, ProcessingType, Processor, ReadPktUntilFlush, SymmetricProcessor,
+};
+
+/// Env variable, which makes [`Transcript::assert_golden`] overwrite golden files
+pub const BLESS_ENV: &str = "GIT_FILTER_SERVER_BLESS";
+
+/// Prerecorded client side of the session
+///
+/// As input is fixed, it should anticipate server responses, i.e which files
+/// are delayed, and what is listed by `list_available_blobs`
+pub struct ScriptedClient {
+    data: Vec<u8>,
+}
+
+impl ScriptedClient {
+    /// Start session with hello and capability negotiation
+    pub fn new(capabilities: Capabilities) -> Self {
+        let mut data = Vec::new();
+        data.pkt_text_write("git-filter-client").unwrap();
+        data.pkt_text_write("version=2").unwrap();
+        data.pkt_end().unwrap();
+        capabilities.write(&mut data).unwrap();
+        data.pkt_end().unwrap();
+        Self { data }
+    }
+
+    /// Request processing of file, with extra header lines after pathname
+    pub fn command_with_headers(
+        mut self,
+        process_type: ProcessingType,
+        pathname: impl AsRef<[u8]>,
+        headers: &[&str],
+        content: &[u8],
+    ) -> Self {
+        file_command(
+            &mut self.data,
+            process_type,
+            pathname.as_ref(),
+            headers,
+            content,
+            MAX_PKT_SIZE,
+        )
+        .unwrap();
+        self
+    }
+
+    /// Request processing of file, sending content in records of `record_size` bytes
+    pub fn command_in_records(
+        mut self,
+        process_type: ProcessingType,
+        pathname: impl AsRef<[u8]>,
+        content: &[u8],
+        record_size: usize,
+    ) -> Self {
+        file_command(
+            &mut self.data,
+            process_type,
+            pathname.as_ref(),
+            &[],
+            content,
+            record_size.clamp(1, MAX_PKT_SIZE),
+        )
+        .unwrap();
+        self
+    }
+
+    /// Request processing of file, pathname isn't required to be valid UTF-8
+    pub fn command(
+        self,
+        process_type: ProcessingType,
+        pathname: impl AsRef<[u8]>,
+        content: &[u8],
+    ) -> Self {
+        self.command_with_headers(process_type, pathname, &[], content)
+    }
+
+    /// Request processing of file, allowing server to delay it
+    pub fn delayable_command(
+        self,
+        process_type: ProcessingType,
+        pathname: impl AsRef<[u8]>,
+        content: &[u8],
+    ) -> Self {
+        self.command_with_headers(process_type, pathname, &["can-delay=1"], content)
+    }
+
+    /// Request previously delayed file, after it was listed as available
+    pub fn delayed_request(self, process_type: ProcessingType, pathname: impl AsRef<[u8]>) -> Self {
+        self.command_with_headers(process_type, pathname, &[], &[])
+    }
+
+    pub fn list_available_blobs(mut self) -> Self {
+        self.data
+            .pkt_text_write("command=list_available_blobs")
+            .unwrap();
+        self.data.pkt_end().unwrap();
+        self
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Bytes sent by both sides of the session
+pub struct Transcript {
+    pub client: Vec<u8>,
+    pub server: Vec<u8>,
+}
+
+impl Transcript {
+    /// Run scripted session against the server, until script ends
+    pub fn record<P: Processor>(
+        server: &mut GitFilterServer<P>,
+        client: ScriptedClient,
+    ) -> Result<Self> {
+        let client = client.into_bytes();
+        let mut output = Vec::new();
+        server.communicate(&mut client.as_slice(), &mut output)?;
+        Ok(Self {
+            client,
+            server: output,
+        })
+    }
+
+    /// Human readable form, every pkt record is rendered on its own line, with exact length
+    pub fn render(&self) -> String {
+        format!(
+            "# client\n{}# server\n{}",
+            render_pkt_stream(&self.client),
+            render_pkt_stream(&self.server)
+        )
+    }
+
+    /// Compare rendered transcript with golden file, or overwrite it if [`BLESS_ENV`] is set
+    pub fn assert_golden(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        let actual = self.render();
+        if std::env::var_os(BLESS_ENV).is_some() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).expect("failed to create golden file directory");
+            }
+            fs::write(path, &actual).expect("failed to write golden file");
+            return;
+        }
+        let expected = fs::read_to_string(path).unwrap_or_else(|e| {
+            panic!(
+                "failed to read golden file {}: {}, run with {}=1 to create it",
+                path.display(),
+                e,
+                BLESS_ENV
+            )
+        });
+        if expected != actual {
+            panic!(
+                "transcript doesn't match golden file {}, run with {}=1 to update it\n--- expected\n{}--- actual\n{}",
+                path.display(),
+                BLESS_ENV,
+                expected,
+                actual
+            );
+        }
+    }
+}
+
+/// Check, that backward transform restores data after forward transform
+///
+/// Panics with both versions of data otherwise
+pub fn assert_inverse<P: SymmetricProcessor>(processor: &mut P, pathname: &str, data: &[u8]) {
+    let mut forward = Vec::new();
+    processor
+        .forward(&Pathname::from(pathname), &mut &data[..], &mut forward)
+        .expect("forward transform failed");
+    let mut backward = Vec::new();
+    processor
+        .backward(
+            &Pathname::from(pathname),
+            &mut forward.as_slice(),
+            &mut backward,
+        )
+        .expect("backward transform failed");
+    if backward != data {
+        panic!(
+            "backward transform doesn't restore {}\n--- original\n{}\n--- restored\n{}",
+            pathname,
+            data.escape_ascii(),
+            backward.escape_ascii()
+        );
+    }
+}
+
+/// Reader, which panics if it is read again after reporting end of input
+///
+/// [`crate::ReadPktUntilFlush`] keeps returning 0 after flush, so processor with buggy
+/// read loop silently works on truncated data, wrap input with this to catch it
+pub struct StrictReader<R> {
+    read: R,
+    ended: bool,
+}
+
+impl<R> StrictReader<R> {
+    pub fn new(read: R) -> Self {
+        Self { read, ended: false }
+    }
+
+    /// Was end of input already reported
+    pub fn ended(&self) -> bool {
+        self.ended
+    }
+
+    pub fn into_inner(self) -> R {
+        self.read
+    }
+}
+
+impl<R: Read> Read for StrictReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        assert!(!self.ended, "input was read after it ended");
+        let read = self.read.read(buf)?;
+        if read == 0 {
+            self.ended = true;
+        }
+        Ok(read)
+    }
+}
+
+/// Render pkt-line stream, one record per line
+pub fn render_pkt_stream(mut data: &[u8]) -> String {
+    let mut out = String::new();
+    while !data.is_empty() {
+        let len = match data
+            .get(..4)
+            .and_then(|len| std::str::from_utf8(len).ok())
+            .and_then(|len| usize::from_str_radix(len, 16).ok())
+        {
+            Some(len) => len,
+            None => {
+                writeln!(out, "<bad length> {}", data.escape_ascii()).unwrap();
+                break;
+            }
+        };
+        if len == 0 {
+            out.push_str("0000\n");
+            data = &data[4..];
+            continue;
+        }
+        match data.get(4..len) {
+            Some(payload) if len >= 4 => {
+                writeln!(out, "{:04x} {}", len, payload.escape_ascii()).unwrap();
+                data = &data[len..];
+            }
+            _ => {
+                writeln!(out, "<truncated> {}", data.escape_ascii()).unwrap();
+                break;
+            }
+        }
+    }
+    out
+}
+
+struct PipeState {
+    buffer: VecDeque<u8>,
+    capacity: usize,
+    writer_closed: bool,
+    reader_closed: bool,
+}
+
+struct Pipe {
+    state: Mutex<PipeState>,
+    changed: Condvar,
+}
+
+/// In-memory pipe with fixed capacity, writer blocks until reader consumes data
+///
+/// Small capacity makes deadlocks between slow reader and writer reproducible
+pub fn pipe(capacity: usize) -> (PipeWriter, PipeReader) {
+    assert!(capacity > 0, "pipe capacity should be positive");
+    let pipe = Arc::new(Pipe {
+        state: Mutex::new(PipeState {
+            buffer: VecDeque::with_capacity(capacity),
+            capacity,
+            writer_closed: false,
+            reader_closed: false,
+        }),
+        changed: Condvar::new(),
+    });
+    (PipeWriter(pipe.clone()), PipeReader(pipe))
+}
+
+/// Write end of [`pipe`], closes pipe on drop
+pub struct PipeWriter(Arc<Pipe>);
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.0.state.lock().unwrap();
+        loop {
+            if state.reader_closed {
+                return Err(io::Error::new(
+                    ErrorKind::BrokenPipe,
+                    "pipe reader is closed",
+                ));
+            }
+            if state.buffer.len() < state.capacity {
+                break;
+            }
+            state = self.0.changed.wait(state).unwrap();
+        }
+        let written = (state.capacity - state.buffer.len()).min(buf.len());
+        state.buffer.extend(&buf[..written]);
+        self.0.changed.notify_all();
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().writer_closed = true;
+        self.0.changed.notify_all();
+    }
+}
+
+/// Read end of [`pipe`], returns EOF once writer is dropped and buffer is drained
+pub struct PipeReader(Arc<Pipe>);
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.0.state.lock().unwrap();
+        while state.buffer.is_empty() && !state.writer_closed {
+            state = self.0.changed.wait(state).unwrap();
+        }
+        let read = state.buffer.len().min(buf.len());
+        for (out, byte) in buf.iter_mut().zip(state.buffer.drain(..read)) {
+            *out = byte;
+        }
+        self.0.changed.notify_all();
+        Ok(read)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().reader_closed = true;
+        self.0.changed.notify_all();
+    }
+}
+
+/// Server session running on background thread, see [`spawn_server`]
+pub struct ServerHandle<P> {
+    handle: JoinHandle<(GitFilterServer<P>, Result<()>)>,
+}
+
+impl<P> ServerHandle<P> {
+    /// Wait for session to end, client should drop its writer first
+    pub fn join(self) -> (GitFilterServer<P>, Result<()>) {
+        self.handle.join().expect("server thread panicked")
+    }
+}
+
+/// Run server session on background thread, connected to client with bounded pipes
+///
+/// Returns writer to server input and reader of server output
+pub fn spawn_server<P: Processor + Send + 'static>(
+    mut server: GitFilterServer<P>,
+    capacity: usize,
+) -> (ServerHandle<P>, PipeWriter, PipeReader) {
+    let (client_writer, mut server_reader) = pipe(capacity);
+    let (mut server_writer, client_reader) = pipe(capacity);
+    let handle = thread::spawn(move || {
+        let result = server.communicate(&mut server_reader, &mut server_writer);
+        (server, result)
+    });
+    (ServerHandle { handle }, client_writer, client_reader)
+}
+
+/// Clock, which only moves when advanced manually, or slept on
+///
+/// Sleeping returns immediately, advancing clock by requested duration.
+/// Clones share the same time, so test can keep handle to clock given to server
+#[derive(Clone)]
+pub struct ManualClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    /// Time passed since clock was created
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Response of server to single file command, as seen by git
+enum FileResponse {
+    Content(Vec<u8>),
+    Delayed,
+    Failed,
+}
+
+/// Read value of last `status=` line of status list
+fn read_status<R: Read>(input: &mut R) -> Result<Option<String>> {
+    let mut buf = Vec::new();
+    let mut status = None;
+    while let Some(line) = input.pkt_text_read(&mut buf)? {
+        if let Some(value) = line.strip_prefix("status=") {
+            status = Some(value.to_owned());
+        }
+    }
+    Ok(status)
+}
+
+fn read_file_response<R: Read>(input: &mut R) -> Result<FileResponse> {
+    match read_status(input)?.as_deref() {
+        Some("success") => {}
+        Some("delayed") => return Ok(FileResponse::Delayed),
+        Some("error") | Some("abort") => return Ok(FileResponse::Failed),
+        Some(status) => return Err(parse_error!(format!("unknown status: {}", status))),
+        None => return Err(parse_error!("missing status")),
+    }
+    let mut content = Vec::new();
+    ReadPktUntilFlush::new(&mut *input).read_to_end(&mut content)?;
+    // Empty list keeps previous status
+    match read_status(input)?.as_deref() {
+        None | Some("success") => Ok(FileResponse::Content(content)),
+        Some(_) => Ok(FileResponse::Failed),
+    }
+}
+
+/// Simulates `git checkout` talking to filter, so full protocol can be tested without git
+///
+/// Smudge and delay are offered, every file is sent with `can-delay=1` (if delay was
+/// negotiated), then delayed files are collected in `list_available_blobs` rounds,
+/// until server lists nothing. Files left pending at that point are failed, as git does.
+///
+/// Unlike [`ScriptedClient`], reacts to actual responses
+pub struct GitSimulator {
+    capabilities: Capabilities,
+    files: Vec<(Pathname, Vec<u8>)>,
+    pipe_capacity: usize,
+}
+
+/// Result of [`GitSimulator::checkout`]
+pub struct Checkout<P> {
+    /// Smudged content of every successfully checked out file
+    pub working_tree: BTreeMap<Pathname, Vec<u8>>,
+    /// Files, which server failed, or never delivered after delaying
+    pub failed: Vec<Pathname>,
+    /// Count of `list_available_blobs` commands sent
+    pub delay_rounds: usize,
+    /// Server after session end, i.e for inspecting its summary
+    pub server: GitFilterServer<P>,
+}
+
+impl GitSimulator {
+    pub fn new() -> Self {
+        Self {
+            capabilities: Capabilities {
+                clean: false,
+                smudge: true,
+                delay: true,
+            },
+            files: Vec::new(),
+            pipe_capacity: 64 * 1024,
+        }
+    }
+
+    /// Don't offer delay capability, like git versions before 2.15
+    pub fn without_delay(mut self) -> Self {
+        self.capabilities.delay = false;
+        self
+    }
+
+    /// Capacity of pipes between simulator and server, see [`pipe`]
+    pub fn pipe_capacity(mut self, capacity: usize) -> Self {
+        self.pipe_capacity = capacity;
+        self
+    }
+
+    /// Add file to checkout, `content` is blob as stored in repository
+    pub fn file(mut self, pathname: impl Into<Pathname>, content: &[u8]) -> Self {
+        self.files.push((pathname.into(), content.to_vec()));
+        self
+    }
+
+    /// Run whole checkout against server on background thread
+    ///
+    /// Fails if server breaks protocol, or session itself fails
+    pub fn checkout<P: Processor + Send + 'static>(
+        self,
+        server: GitFilterServer<P>,
+    ) -> Result<Checkout<P>> {
+        let (handle, mut output, mut input) = spawn_server(server, self.pipe_capacity);
+        let mut working_tree = BTreeMap::new();
+        let mut failed = Vec::new();
+        let mut delay_rounds = 0;
+        let result = (|| {
+            let negotiated =
+                ClientHandshake::new(self.capabilities).perform(&mut input, &mut output)?;
+            if !negotiated.smudge {
+                return Err(parse_error!("server didn't advertise smudge"));
+            }
+            let mut pending = Vec::new();
+            for (pathname, content) in &self.files {
+                file_command(
+                    &mut output,
+                    ProcessingType::Smudge,
+                    pathname.as_bytes(),
+                    if negotiated.delay {
+                        &["can-delay=1"]
+                    } else {
+                        &[]
+                    },
+                    content,
+                    MAX_PKT_SIZE,
+                )?;
+                match read_file_response(&mut input)? {
+                    FileResponse::Content(content) => {
+                        working_tree.insert(pathname.clone(), content);
+                    }
+                    FileResponse::Delayed => pending.push(pathname.clone()),
+                    FileResponse::Failed => failed.push(pathname.clone()),
+                }
+            }
+            // Like git, keep asking until server lists nothing, once anything was delayed
+            let mut delayed = !pending.is_empty();
+            while delayed {
+                output.pkt_text_write("command=list_available_blobs")?;
+                output.pkt_end()?;
+                delay_rounds += 1;
+                let available = read_available_blobs(&mut input)?;
+                if available.is_empty() {
+                    // Git reports them as not filtered properly
+                    failed.append(&mut pending);
+                    delayed = false;
+                }
+                for pathname in available {
+                    let index = pending.iter().position(|p| *p == pathname).ok_or_else(|| {
+                        parse_error!(format!("server listed unknown blob: {}", pathname))
+                    })?;
+                    pending.remove(index);
+                    file_command(
+                        &mut output,
+                        ProcessingType::Smudge,
+                        pathname.as_bytes(),
+                        &[],
+                        &[],
+                        MAX_PKT_SIZE,
+                    )?;
+                    match read_file_response(&mut input)? {
+                        FileResponse::Content(content) => {
+                            working_tree.insert(pathname, content);
+                        }
+                        FileResponse::Delayed => {
+                            return Err(parse_error!("delayed blob was delayed again"))
+                        }
+                        FileResponse::Failed => failed.push(pathname),
+                    }
+                }
+            }
+            Ok(())
+        })();
+        drop(output);
+        drop(input);
+        let (server, server_result) = handle.join();
+        result?;
+        server_result?;
+        Ok(Checkout {
+            working_tree,
+            failed,
+            delay_rounds,
+            server,
+        })
+    }
+}
+
+impl Default for GitSimulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write file command as git does, with raw pathname bytes
+fn file_command<W: Write>(
+    output: &mut W,
+    process_type: ProcessingType,
+    pathname: &[u8],
+    headers: &[&str],
+    content: &[u8],
+    record_size: usize,
+) -> Result<()> {
+    output.pkt_text_write(&format!("command={}", process_type.name()))?;
+    let mut line = b"pathname=".to_vec();
+    line.extend_from_slice(pathname);
+    line.push(b'\n');
+    output.pkt_bin_write(&line)?;
+    for header in headers {
+        output.pkt_text_write(header)?;
+    }
+    output.pkt_end()?;
+    for record in content.chunks(record_size) {
+        output.pkt_bin_write(record)?;
+    }
+    output.pkt_end()
+}
@@ -0,0 +1,67 @@
+use crate::{Pathname, ProcessingType, Processor};
+use anyhow::{Context, Result};
+use std::io::{self, Read, Write};
+
+/// Remote storage, receiving content of cleaned files, see [`UploadingCleanProcessor`]
+pub trait Uploader {
+    type Upload: Write;
+
+    /// Start upload of file content
+    fn begin(&mut self, pathname: &Pathname) -> Result<Self::Upload>;
+
+    /// Complete upload of `size` bytes, returns pointer, which is stored in git instead of content
+    fn finish(&mut self, pathname: &Pathname, upload: Self::Upload, size: u64) -> Result<Vec<u8>>;
+
+    /// Discard incomplete upload, after input or upload itself failed mid-stream
+    fn abort(&mut self, _pathname: &Pathname, _upload: Self::Upload) {}
+}
+
+/// Clean filter, which streams content to [`Uploader`], and stores returned pointer in git
+///
+/// Content is never buffered as a whole. Once upload fails, it is aborted and file is
+/// reported with `status=error`, nothing is written to output, so partial pointer can't
+/// reach git. Only clean is supported, combine with smudge using [`crate::SplitProcessor`]
+pub struct UploadingCleanProcessor<U> {
+    pub uploader: U,
+}
+
+impl<U> UploadingCleanProcessor<U> {
+    pub fn new(uploader: U) -> Self {
+        Self { uploader }
+    }
+}
+
+impl<U: Uploader> Processor for UploadingCleanProcessor<U> {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        pathname: &Pathname,
+        _process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        let mut upload = self
+            .uploader
+            .begin(pathname)
+            .context("upload start failed")?;
+        let size = match io::copy(input, &mut upload).and_then(|size| {
+            upload.flush()?;
+            Ok(size)
+        }) {
+            Ok(size) => size,
+            Err(e) => {
+                self.uploader.abort(pathname, upload);
+                return Err(e).context("upload failed");
+            }
+        };
+        let pointer = self
+            .uploader
+            .finish(pathname, upload, size)
+            .context("upload finish failed")?;
+        output.write_all(&pointer)?;
+        Ok(())
+    }
+
+    fn supports_processing(&self, process_type: ProcessingType) -> bool {
+        process_type == ProcessingType::Clean
+    }
+}
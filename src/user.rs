@@ -0,0 +1,14 @@
+use std::io::Write;
+
+/// Show message to user of git command
+///
+/// Git doesn't capture stderr of filter process, so message appears in the same terminal
+/// as git own output. Stdout is never touched, as it is occupied by protocol.
+/// Unlike tracing logs, which may be filtered or redirected, message is always written,
+/// errors are ignored, since there is no other channel to report them.
+pub fn warn_user(message: &str) {
+    let stderr = std::io::stderr();
+    let mut stderr = stderr.lock();
+    let _ = writeln!(stderr, "{}", message);
+    let _ = stderr.flush();
+}
@@ -1,12 +1,29 @@
-use crate::ext::{ReadExt, WriteExt, MAX_PKT_SIZE};
-use std::io::{Read, Result, Write};
+use crate::ext::{retry_interrupted, ReadExt, WriteExt, MAX_PKT_SIZE};
+use crate::parse_error;
+use std::{
+    io::{Error, ErrorKind, Read, Result, Write},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Duration,
+};
 
 /// Writes to inner buffer, wrapping input with pkt format
-/// Doesn't sends flush sequences (0000)
+/// Doesn't sends flush sequences (0000), except for deferred status
+///
+/// Content is terminated by single flush, so it can't be sent mid-file,
+/// flushing this writer only sends buffered partial record
 pub struct WritePkt<W: Write> {
     buffer: Vec<u8>,
     write: W,
     written: u64,
+    max_size: Option<u64>,
+    hint_limit: Option<u64>,
+    status_pending: bool,
+    status_sent: bool,
+    record_size: usize,
+    fixed_records: bool,
+    buffer_size: Option<usize>,
+    failed: Option<ErrorKind>,
 }
 impl<W: Write> WritePkt<W> {
     pub fn new(write: W) -> Self {
@@ -14,16 +31,108 @@ impl<W: Write> WritePkt<W> {
             buffer: Vec::new(),
             write,
             written: 0,
+            max_size: None,
+            hint_limit: None,
+            status_pending: false,
+            status_sent: false,
+            record_size: MAX_PKT_SIZE,
+            fixed_records: false,
+            buffer_size: None,
+            failed: None,
         }
     }
-    #[allow(dead_code)]
+    /// Emit records of exactly `size` bytes, except for the last one
+    ///
+    /// [`Write::flush`] then doesn't send partial records, so output bytes don't depend
+    /// on how writes are split, use [`WritePkt::finish`] to send the rest.
+    /// Without it, records are up to MAX_PKT_SIZE, and partial record is sent on every flush
+    pub fn with_record_size(mut self, size: Option<usize>) -> Self {
+        if let Some(size) = size {
+            self.record_size = size.clamp(1, MAX_PKT_SIZE);
+            self.fixed_records = true;
+        }
+        self
+    }
+    /// Collect up to `size` bytes before sending them, instead of single record
+    ///
+    /// Larger buffer results in fewer writes to underlying stream, at cost of memory.
+    /// Buffer is never smaller than record size, with fixed size records only whole
+    /// records are sent once buffer is full
+    pub fn with_buffer_size(mut self, size: Option<usize>) -> Self {
+        self.buffer_size = size;
+        self
+    }
+    fn capacity(&self) -> usize {
+        self.buffer_size.unwrap_or(0).max(self.record_size)
+    }
+    /// Send `status=success` list right before first data record
+    ///
+    /// Git only starts reading response after sending whole input, so nothing should
+    /// be written before processor consumes it, or both sides may block on full pipes
+    pub fn with_deferred_status(mut self) -> Self {
+        self.status_pending = true;
+        self
+    }
+    /// Was deferred status already sent
+    pub fn status_sent(&self) -> bool {
+        self.status_sent
+    }
+    /// Fail writing with InvalidData once more than `max_size` bytes are written
+    pub fn with_max_size(mut self, max_size: Option<u64>) -> Self {
+        self.max_size = max_size;
+        self
+    }
+    /// Fail writing with InvalidData once more than `limit` bytes are written,
+    /// where limit is size processor committed to, plus tolerance
+    pub fn with_hint_limit(mut self, limit: Option<u64>) -> Self {
+        self.hint_limit = limit;
+        self
+    }
+    /// Number of bytes sent to inner writer
     pub fn written(&self) -> u64 {
         self.written
     }
-    fn flush_buf(&mut self) -> Result<()> {
-        self.write.pkt_bin_write(&self.buffer)?;
-        self.written = self.written.saturating_add(self.buffer.len() as u64);
-        self.buffer.truncate(0);
+    /// Send everything buffered, including partial fixed size record
+    pub fn finish(&mut self) -> Result<()> {
+        #[cfg(feature = "alloc-stats")]
+        let _phase = crate::AllocPhase::Write.enter();
+        self.flush_buf(false)?;
+        retry_interrupted(|| self.write.flush())
+    }
+    /// Send buffered data, keeping partial record if `whole_records` is set
+    ///
+    /// Once inner writer fails, part of record might be already sent, so buffer
+    /// is never sent again, and every following call fails with the same kind
+    fn flush_buf(&mut self, whole_records: bool) -> Result<()> {
+        if let Some(kind) = self.failed {
+            return Err(Error::new(kind, "output stream failed earlier"));
+        }
+        let result = self.send_buf(whole_records);
+        if let Err(e) = &result {
+            self.failed = Some(e.kind());
+        }
+        result
+    }
+    fn send_buf(&mut self, whole_records: bool) -> Result<()> {
+        let send = if whole_records {
+            self.buffer.len() - self.buffer.len() % self.record_size
+        } else {
+            self.buffer.len()
+        };
+        if send == 0 {
+            return Ok(());
+        }
+        if self.status_pending {
+            self.write.pkt_text_write("status=success")?;
+            self.write.pkt_end()?;
+            self.status_pending = false;
+            self.status_sent = true;
+        }
+        for record in self.buffer[..send].chunks(self.record_size) {
+            self.write.pkt_bin_write(record)?;
+        }
+        self.written = self.written.saturating_add(send as u64);
+        self.buffer.drain(..send);
         Ok(())
     }
 }
@@ -32,13 +141,27 @@ impl<W: Write> Write for WritePkt<W> {
         if buf.is_empty() {
             return Ok(0);
         }
+        #[cfg(feature = "alloc-stats")]
+        let _phase = crate::AllocPhase::Write.enter();
         let len = buf.len();
+        let total = self
+            .written
+            .saturating_add((self.buffer.len() + len) as u64);
+        if self.max_size.is_some_and(|max_size| total > max_size) {
+            return Err(parse_error!("max output size exceeded"));
+        }
+        if self.hint_limit.is_some_and(|limit| total > limit) {
+            return Err(parse_error!("output size hint exceeded"));
+        }
+        let capacity = self.capacity();
+        if self.buffer.capacity() < capacity {
+            self.buffer.reserve_exact(capacity - self.buffer.len());
+        }
         while !buf.is_empty() {
-            let to_write = (MAX_PKT_SIZE - self.buffer.len()).min(buf.len());
-            self.buffer.reserve(to_write);
-            self.buffer.write_all(&buf[..to_write]).unwrap();
-            if self.buffer.len() == MAX_PKT_SIZE {
-                self.flush_buf()?;
+            let to_write = (capacity - self.buffer.len()).min(buf.len());
+            self.buffer.extend_from_slice(&buf[..to_write]);
+            if self.buffer.len() == capacity {
+                self.flush_buf(self.fixed_records)?;
             }
             buf = &buf[to_write..];
         }
@@ -46,63 +169,145 @@ impl<W: Write> Write for WritePkt<W> {
     }
 
     fn flush(&mut self) -> Result<()> {
-        self.flush_buf()?;
-        self.write.flush()
+        #[cfg(feature = "alloc-stats")]
+        let _phase = crate::AllocPhase::Write.enter();
+        if !self.fixed_records {
+            self.flush_buf(false)?;
+        }
+        retry_interrupted(|| self.write.flush())
     }
 }
 
 impl<W: Write> Drop for WritePkt<W> {
     fn drop(&mut self) {
-        if !self.buffer.is_empty() {
+        // Don't turn unwinding processor panic into abort, and unsent data
+        // is expected after inner writer failure
+        if !self.buffer.is_empty() && self.failed.is_none() && !thread::panicking() {
             panic!("WritePkt was not flushed before drop")
         }
     }
 }
 
+/// Collects output in memory, failing with InvalidData once more than `max_size` bytes
+/// are written, same as [`WritePkt::with_max_size`]
+pub(crate) struct BoundedBuffer {
+    pub data: Vec<u8>,
+    max_size: Option<u64>,
+}
+impl BoundedBuffer {
+    pub fn new(max_size: Option<u64>) -> Self {
+        Self {
+            data: Vec::new(),
+            max_size,
+        }
+    }
+}
+impl Write for BoundedBuffer {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let total = (self.data.len() + buf.len()) as u64;
+        if self.max_size.is_some_and(|max_size| total > max_size) {
+            return Err(parse_error!("max output size exceeded"));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
 /// Reads data in pkt format until receiving flush (0000)
 pub struct ReadPktUntilFlush<R> {
     read: R,
     read_bytes: u64,
+    max_size: Option<u64>,
+    records: u64,
+    max_records: Option<u64>,
     buffer: Vec<u8>,
     offset: usize,
     eof: bool,
+    failed: bool,
 }
 impl<R> ReadPktUntilFlush<R> {
     pub fn new(read: R) -> Self {
         Self {
             read,
             read_bytes: 0,
+            max_size: None,
+            records: 0,
+            max_records: None,
             buffer: Vec::new(),
             offset: 0,
             eof: false,
+            failed: false,
         }
     }
+    /// Fail reading with InvalidData once more than `max_size` bytes are received
+    pub fn with_max_size(mut self, max_size: Option<u64>) -> Self {
+        self.max_size = max_size;
+        self
+    }
+    /// Fail reading with InvalidData once more than `max_records` pkt records are received
+    pub fn with_max_records(mut self, max_records: Option<u64>) -> Self {
+        self.max_records = max_records;
+        self
+    }
+    /// Input reached its end, either by receiving flush, or by failing
+    ///
+    /// After failure, every following read returns 0
     pub fn finished(&self) -> bool {
         self.eof
     }
-    #[allow(dead_code)]
-    pub fn read(&self) -> u64 {
+    /// Input was ended by flush, and not by a failed read of underlying stream
+    /// or exceeded limit
+    pub fn terminated_cleanly(&self) -> bool {
+        self.eof && !self.failed
+    }
+    /// Number of bytes returned to reader
+    pub fn read_bytes(&self) -> u64 {
         self.read_bytes
     }
 }
+impl<R> ReadPktUntilFlush<R> {
+    fn fail(&mut self, e: std::io::Error) -> std::io::Error {
+        self.eof = true;
+        self.failed = true;
+        e
+    }
+}
 impl<R: Read> Read for ReadPktUntilFlush<R> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         if self.eof {
             return Ok(0);
         }
+        #[cfg(feature = "alloc-stats")]
+        let _phase = crate::AllocPhase::Read.enter();
         if self.buffer[self.offset..].is_empty() {
-            match self.read.pkt_bin_read(&mut self.buffer)? {
-                Some(_) => {}
-                None => {
+            match self.read.pkt_bin_read(&mut self.buffer) {
+                Ok(Some(_)) => {}
+                Ok(None) => {
                     // Got flush
                     self.eof = true;
                     return Ok(0);
                 }
+                Err(e) => return Err(self.fail(e)),
             }
             assert!(
                 !self.buffer.is_empty(),
                 "pkt_bin_read never returns empty buffer"
             );
+            self.records += 1;
+            if let Some(max_records) = self.max_records {
+                if self.records > max_records {
+                    return Err(self.fail(parse_error!("max input records exceeded")));
+                }
+            }
+            if let Some(max_size) = self.max_size {
+                if self.read_bytes.saturating_add(self.buffer.len() as u64) > max_size {
+                    return Err(self.fail(parse_error!("max input size exceeded")));
+                }
+            }
             self.offset = 0;
         }
         let data = &self.buffer[self.offset..];
@@ -114,3 +319,115 @@ impl<R: Read> Read for ReadPktUntilFlush<R> {
         Ok(read_bytes)
     }
 }
+
+/// Copies everything read from inner reader to secondary writer, i.e cache
+///
+/// Failure of secondary writer doesn't affect reading, it stops copying instead,
+/// and is reported by [`TeeReader::tee_error`]
+pub struct TeeReader<R, W> {
+    read: R,
+    tee: W,
+    tee_error: Option<Error>,
+}
+impl<R, W> TeeReader<R, W> {
+    pub fn new(read: R, tee: W) -> Self {
+        Self {
+            read,
+            tee,
+            tee_error: None,
+        }
+    }
+    /// Error of secondary writer, data written to it is incomplete if set
+    pub fn tee_error(&self) -> Option<&Error> {
+        self.tee_error.as_ref()
+    }
+    /// Returns inner reader, secondary writer, and its error
+    pub fn into_parts(self) -> (R, W, Option<Error>) {
+        (self.read, self.tee, self.tee_error)
+    }
+}
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.read.read(buf)?;
+        if self.tee_error.is_none() {
+            if let Err(e) = self.tee.write_all(&buf[..read]) {
+                self.tee_error = Some(e);
+            }
+        }
+        Ok(read)
+    }
+}
+
+enum WriteRequest {
+    Write(Vec<u8>),
+    Flush,
+}
+
+/// Performs writes on background thread, failing with TimedOut if single
+/// write or flush doesn't complete in time
+///
+/// After timeout every operation fails, because stuck write may still complete later.
+/// Timeout is measured in real time, regardless of [`crate::Clock`] given to server
+pub struct TimeoutWriter {
+    requests: Sender<WriteRequest>,
+    responses: Receiver<Result<()>>,
+    timeout: Duration,
+    timed_out: bool,
+}
+impl TimeoutWriter {
+    /// Start writer thread, fails if thread can't be spawned
+    pub fn new<W: Write + Send + 'static>(mut write: W, timeout: Duration) -> Result<Self> {
+        let (requests, request_rx) = mpsc::channel();
+        let (response_tx, responses) = mpsc::channel();
+        thread::Builder::new()
+            .name("git-filter-server-writer".to_owned())
+            .spawn(move || {
+                for request in request_rx {
+                    let result = match request {
+                        WriteRequest::Write(data) => write.write_all(&data),
+                        WriteRequest::Flush => retry_interrupted(|| write.flush()),
+                    };
+                    if response_tx.send(result).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok(Self {
+            requests,
+            responses,
+            timeout,
+            timed_out: false,
+        })
+    }
+    fn request(&mut self, request: WriteRequest) -> Result<()> {
+        if self.timed_out {
+            return Err(Error::new(ErrorKind::TimedOut, "previous write timed out"));
+        }
+        self.requests
+            .send(request)
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "writer thread is gone"))?;
+        match self.responses.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                self.timed_out = true;
+                Err(Error::new(ErrorKind::TimedOut, "write timed out"))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(Error::new(ErrorKind::BrokenPipe, "writer thread is gone"))
+            }
+        }
+    }
+}
+impl Write for TimeoutWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.request(WriteRequest::Write(buf.to_vec()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.request(WriteRequest::Flush)
+    }
+}
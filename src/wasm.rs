@@ -0,0 +1,257 @@
+//! Processor delegating to WebAssembly module, for sandboxed user-provided filters
+
+use crate::{Pathname, ProcessingType, Processor};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    convert::TryFrom,
+    io::{Read, Write},
+};
+use wasmi::{
+    Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Val,
+};
+
+/// Instantiated module, implemented for [`WasmiInstance`], and can be implemented
+/// over other WASM runtimes, i.e `wasmtime::Instance` together with its `Store`
+pub trait WasmInstance {
+    /// Call exported function, trap should be returned as error
+    fn call(&mut self, name: &str, args: &[i32]) -> Result<i32>;
+
+    /// Copy bytes from linear memory at `offset`, out of bounds access should fail
+    fn read_memory(&mut self, offset: u32, buf: &mut [u8]) -> Result<()>;
+
+    /// Copy bytes to linear memory at `offset`, out of bounds access should fail
+    fn write_memory(&mut self, offset: u32, data: &[u8]) -> Result<()>;
+}
+
+/// Resources module is allowed to use, see [`WasmiInstance::new`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmLimits {
+    /// Max size of linear memory in bytes, module can't grow its memory past it
+    pub max_memory: usize,
+    /// Fuel given to every call of exported function, roughly count of executed
+    /// instructions. Call, which runs out of it, fails, so looping module can't hang git
+    pub fuel_per_call: Option<u64>,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            max_memory: 64 * 1024 * 1024,
+            fuel_per_call: None,
+        }
+    }
+}
+
+/// Module instantiated in [wasmi](https://docs.rs/wasmi) interpreter
+///
+/// Module can't import anything, so it has no access to host, and only sees
+/// data passed through its transfer buffer. It has to export its memory as `memory`
+pub struct WasmiInstance {
+    store: Store<StoreLimits>,
+    instance: Instance,
+    memory: Memory,
+    fuel_per_call: Option<u64>,
+}
+
+impl WasmiInstance {
+    /// Compile and instantiate module from binary format
+    pub fn new(wasm: &[u8], limits: WasmLimits) -> Result<Self> {
+        let mut config = Config::default();
+        config.consume_fuel(limits.fuel_per_call.is_some());
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm).context("failed to load module")?;
+        if let Some(import) = module.imports().next() {
+            bail!(
+                "module imports {}::{}, but no imports are provided",
+                import.module(),
+                import.name()
+            );
+        }
+        let store_limits = StoreLimitsBuilder::new()
+            .memory_size(limits.max_memory)
+            .memories(1)
+            .build();
+        let mut store = Store::new(&engine, store_limits);
+        store.limiter(|limits| limits);
+        if let Some(fuel) = limits.fuel_per_call {
+            store.set_fuel(fuel)?;
+        }
+        let instance = Linker::new(&engine)
+            .instantiate_and_start(&mut store, &module)
+            .context("failed to instantiate module")?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .context("module doesn't export memory")?;
+        Ok(Self {
+            store,
+            instance,
+            memory,
+            fuel_per_call: limits.fuel_per_call,
+        })
+    }
+}
+
+impl WasmInstance for WasmiInstance {
+    fn call(&mut self, name: &str, args: &[i32]) -> Result<i32> {
+        let func = self
+            .instance
+            .get_func(&self.store, name)
+            .ok_or_else(|| anyhow!("module doesn't export {}", name))?;
+        if let Some(fuel) = self.fuel_per_call {
+            self.store.set_fuel(fuel)?;
+        }
+        let args: Vec<Val> = args.iter().copied().map(Val::I32).collect();
+        let mut result = [Val::I32(0)];
+        func.call(&mut self.store, &args, &mut result)
+            .with_context(|| format!("{} trapped", name))?;
+        match result[0] {
+            Val::I32(value) => Ok(value),
+            _ => bail!("{} returned non-i32 value", name),
+        }
+    }
+
+    fn read_memory(&mut self, offset: u32, buf: &mut [u8]) -> Result<()> {
+        self.memory.read(&self.store, offset as usize, buf)?;
+        Ok(())
+    }
+
+    fn write_memory(&mut self, offset: u32, data: &[u8]) -> Result<()> {
+        self.memory.write(&mut self.store, offset as usize, data)?;
+        Ok(())
+    }
+}
+
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+fn op_code(process_type: ProcessingType) -> i32 {
+    match process_type {
+        ProcessingType::Clean => 0,
+        ProcessingType::Smudge => 1,
+    }
+}
+
+/// Processor, which runs clean/smudge inside of WASM module
+///
+/// # ABI
+///
+/// Module exports its linear memory and following functions, every argument and
+/// result is `i32`. Operation code is 0 for clean and 1 for smudge, status is
+/// 0 on success, and negative on failure.
+///
+/// - `filter_supports(op) -> i32`: nonzero if module implements operation
+/// - `filter_buffer() -> ptr`, `filter_buffer_size() -> len`: transfer buffer in module
+///   memory, all data is passed through it in chunks of at most `min(len, 65536)` bytes,
+///   in both directions
+/// - `filter_begin(op, pathname_len) -> status`: start new file, buffer holds pathname,
+///   exactly as sent by git
+/// - `filter_input(len) -> status`: buffer holds next `len` bytes of input
+/// - `filter_finish() -> status`: input is over
+/// - `filter_output() -> len`: put next chunk of output to buffer, and return its length,
+///   0 once output is over, negative on failure
+///
+/// Host calls `filter_begin`, then `filter_input` for every chunk of input, then
+/// `filter_finish`, and then `filter_output` until it returns 0. Module only has to
+/// keep state of single file at a time. Failed file is abandoned by host,
+/// next file starts with `filter_begin` again
+///
+/// Module is run in wasmi by [`WasmProcessor::load`], other runtimes can be used
+/// by implementing [`WasmInstance`] over them
+pub struct WasmProcessor<I> {
+    instance: I,
+    buffer: u32,
+    buffer_size: usize,
+    clean: bool,
+    smudge: bool,
+}
+
+impl<I: WasmInstance> WasmProcessor<I> {
+    /// Query transfer buffer and supported operations of module
+    pub fn new(mut instance: I) -> Result<Self> {
+        let buffer = instance.call("filter_buffer", &[])?;
+        let buffer = u32::try_from(buffer).context("bad transfer buffer address")?;
+        let buffer_size = instance.call("filter_buffer_size", &[])?;
+        // Chunks are also buffered on host side, so untrusted module can't make it allocate much
+        let buffer_size = match usize::try_from(buffer_size) {
+            Ok(size) if size > 0 => size.min(MAX_CHUNK_SIZE),
+            _ => bail!("bad transfer buffer size: {}", buffer_size),
+        };
+        let clean = instance.call("filter_supports", &[op_code(ProcessingType::Clean)])? != 0;
+        let smudge = instance.call("filter_supports", &[op_code(ProcessingType::Smudge)])? != 0;
+        Ok(Self {
+            instance,
+            buffer,
+            buffer_size,
+            clean,
+            smudge,
+        })
+    }
+
+    pub fn into_instance(self) -> I {
+        self.instance
+    }
+
+    fn call_status(&mut self, name: &str, args: &[i32]) -> Result<()> {
+        let status = self.instance.call(name, args)?;
+        if status < 0 {
+            bail!("{} failed with status {}", name, status);
+        }
+        Ok(())
+    }
+}
+
+impl WasmProcessor<WasmiInstance> {
+    /// Load module from binary format, and run it in wasmi interpreter
+    pub fn load(wasm: &[u8], limits: WasmLimits) -> Result<Self> {
+        Self::new(WasmiInstance::new(wasm, limits)?)
+    }
+}
+
+impl<I: WasmInstance> Processor for WasmProcessor<I> {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        pathname: &Pathname,
+        process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        let pathname = pathname.as_bytes();
+        if pathname.len() > self.buffer_size {
+            bail!("pathname doesn't fit in module transfer buffer");
+        }
+        self.instance.write_memory(self.buffer, pathname)?;
+        self.call_status(
+            "filter_begin",
+            &[op_code(process_type), pathname.len() as i32],
+        )?;
+
+        let mut chunk = vec![0; self.buffer_size];
+        loop {
+            let read = input.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            self.instance.write_memory(self.buffer, &chunk[..read])?;
+            self.call_status("filter_input", &[read as i32])?;
+        }
+        self.call_status("filter_finish", &[])?;
+
+        loop {
+            let len = self.instance.call("filter_output", &[])?;
+            let len = match usize::try_from(len) {
+                Ok(0) => return Ok(()),
+                Ok(len) if len <= self.buffer_size => len,
+                Ok(len) => bail!("module output chunk exceeds transfer buffer: {}", len),
+                Err(_) => bail!("filter_output failed with status {}", len),
+            };
+            self.instance.read_memory(self.buffer, &mut chunk[..len])?;
+            output.write_all(&chunk[..len])?;
+        }
+    }
+
+    fn supports_processing(&self, process_type: ProcessingType) -> bool {
+        match process_type {
+            ProcessingType::Clean => self.clean,
+            ProcessingType::Smudge => self.smudge,
+        }
+    }
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use std::io::{BufReader, Read, Write};
+
+use common::{within, Generating, Reversing};
+use git_filter_server::{
+    testing::{render_pkt_stream, spawn_server, GitSimulator, ScriptedClient},
+    Capabilities, GitFilterServer, Pathname, ProcessingType,
+};
+
+#[test]
+fn status_is_deferred_until_input_is_consumed() {
+    let content = vec![b'x'; 4096];
+    let client = ScriptedClient::new(Capabilities::all())
+        .command(ProcessingType::Smudge, "a", &content)
+        .into_bytes();
+    // Handshake response fits, but not status after it, so server writing status
+    // before reading input would block against git still writing input
+    let (handle, mut input, mut output) =
+        spawn_server(GitFilterServer::new(Reversing::default()), 100);
+    within(10, move || {
+        input.write_all(&client).unwrap();
+    });
+    let mut response = Vec::new();
+    output.read_to_end(&mut response).unwrap();
+    handle.join().1.unwrap();
+    let response = render_pkt_stream(&response);
+    assert!(
+        response.contains("0013 status=success\\n\n0000\n"),
+        "{}",
+        response
+    );
+}
+
+#[test]
+fn large_output_smudge_doesnt_deadlock() {
+    let content: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let expected: Vec<u8> = content.iter().rev().copied().collect();
+    let checkout = within(30, move || {
+        GitSimulator::new()
+            .without_delay()
+            .pipe_capacity(1024)
+            .file("large", &content)
+            .file("small", b"abc")
+            .checkout(GitFilterServer::new(Reversing::default()))
+            .unwrap()
+    });
+    assert!(checkout.failed.is_empty(), "{:?}", checkout.failed);
+    assert!(checkout.working_tree[&Pathname::from("large")] == expected);
+    assert_eq!(checkout.working_tree[&Pathname::from("small")], b"cba");
+}
+
+#[test]
+fn buffered_output_streams_large_file_to_slow_reader() {
+    const SIZE: usize = 256 * 1024 * 1024;
+    let client = ScriptedClient::new(Capabilities::all())
+        .command(ProcessingType::Smudge, "large", b"abc")
+        .into_bytes();
+    let server = GitFilterServer::new(Generating { size: SIZE }).output_buffer_size(256 * 1024);
+    let (handle, mut input, output) = spawn_server(server, 1024);
+    let (data, text) = within(120, move || {
+        input.write_all(&client).unwrap();
+        drop(input);
+        // Only keep text records, output is too large to collect
+        let mut output = BufReader::new(output);
+        let mut data = 0;
+        let mut text = Vec::new();
+        let mut payload = vec![0; 65516];
+        loop {
+            let mut len = [0; 4];
+            if output.read(&mut len[..1]).unwrap() == 0 {
+                break;
+            }
+            output.read_exact(&mut len[1..]).unwrap();
+            let len = usize::from_str_radix(std::str::from_utf8(&len).unwrap(), 16).unwrap();
+            if len == 0 {
+                continue;
+            }
+            let payload = &mut payload[..len - 4];
+            output.read_exact(payload).unwrap();
+            if payload.iter().all(|&b| b == b'x') {
+                data += payload.len();
+            } else {
+                text.push(String::from_utf8_lossy(payload).into_owned());
+            }
+        }
+        (data, text)
+    });
+    handle.join().1.unwrap();
+    assert_eq!(data, SIZE);
+    assert_eq!(text.last().unwrap(), "status=success\n");
+    assert!(!text.iter().any(|line| line.starts_with("status=error")));
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::Reversing;
+use git_filter_server::{
+    testing::{GitSimulator, ManualClock},
+    GitFilterServer, Pathname,
+};
+
+fn files() -> GitSimulator {
+    GitSimulator::new()
+        .file("immediate", b"abc")
+        .file("delayed", b"def")
+        .file("lost", b"ghi")
+}
+
+#[test]
+fn checkout_gets_immediate_and_delayed_files() {
+    let mut processor = Reversing::delaying();
+    processor.immediate.insert(Pathname::from("immediate"));
+    let checkout = files().checkout(GitFilterServer::new(processor)).unwrap();
+    assert!(checkout.failed.is_empty(), "{:?}", checkout.failed);
+    assert_eq!(checkout.working_tree[&Pathname::from("immediate")], b"cba");
+    assert_eq!(checkout.working_tree[&Pathname::from("delayed")], b"fed");
+    assert_eq!(checkout.working_tree[&Pathname::from("lost")], b"ihg");
+    assert!(checkout.delay_rounds > 0);
+    assert_eq!(checkout.server.summary().files, 3);
+}
+
+#[test]
+fn checkout_fails_abandoned_files() {
+    let clock = ManualClock::new();
+    let mut processor = Reversing::delaying();
+    processor.immediate.insert(Pathname::from("immediate"));
+    processor.lost.insert(Pathname::from("lost"));
+    let server = GitFilterServer::new(processor)
+        .max_empty_polls(3)
+        .clock(clock.clone());
+    let checkout = files().checkout(server).unwrap();
+    assert_eq!(checkout.failed, [Pathname::from("lost")]);
+    assert_eq!(checkout.working_tree.len(), 2);
+    assert_eq!(checkout.working_tree[&Pathname::from("delayed")], b"fed");
+    // Server waited for lost file without real sleeping
+    assert!(clock.elapsed() > std::time::Duration::ZERO);
+    assert_eq!(checkout.server.summary().errors.len(), 1);
+}
+
+#[test]
+fn checkout_without_delay_processes_everything_immediately() {
+    let checkout = files()
+        .without_delay()
+        .checkout(GitFilterServer::new(Reversing::delaying()))
+        .unwrap();
+    assert!(checkout.failed.is_empty(), "{:?}", checkout.failed);
+    assert_eq!(checkout.working_tree.len(), 3);
+    assert_eq!(checkout.delay_rounds, 0);
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::{digests::Fnv64, Reversing};
+use digest::Digest;
+use git_filter_server::{
+    testing::{render_pkt_stream, ScriptedClient, Transcript},
+    Capabilities, GitFilterServer, ProcessingType,
+};
+
+fn server() -> GitFilterServer<Reversing> {
+    GitFilterServer::new(Reversing::delaying()).verify_checksum(|| Box::new(Fnv64::default()))
+}
+
+fn checksum(content: &[u8]) -> String {
+    format!("checksum={}", hex(&Fnv64::digest(content)))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn response(server: &mut GitFilterServer<Reversing>, client: ScriptedClient) -> String {
+    let transcript = Transcript::record(server, client).unwrap();
+    render_pkt_stream(&transcript.server)
+}
+
+#[test]
+fn matching_checksum_is_accepted() {
+    let mut server = server();
+    let client = ScriptedClient::new(Capabilities::all())
+        .command_with_headers(
+            ProcessingType::Smudge,
+            "a",
+            &[&checksum(b"hello")],
+            b"hello",
+        )
+        .command_with_headers(
+            ProcessingType::Smudge,
+            "b",
+            &["can-delay=1", &checksum(b"world")],
+            b"world",
+        );
+    let response = response(&mut server, client);
+    assert!(response.contains("0009 olleh\n"), "{}", response);
+    assert!(response.contains("status=delayed"), "{}", response);
+    assert!(server.summary().errors.is_empty());
+}
+
+#[test]
+fn mismatching_checksum_fails_processed_file() {
+    let mut server = server();
+    let client = ScriptedClient::new(Capabilities::all()).command_with_headers(
+        ProcessingType::Smudge,
+        "a",
+        &[&checksum(b"hello")],
+        b"hellO",
+    );
+    let response = response(&mut server, client);
+    // Content was already streamed, trailing status makes git discard it
+    assert!(
+        response.ends_with("0000\n0011 status=error\\n\n0000\n"),
+        "{}",
+        response
+    );
+    assert_eq!(server.summary().errors.len(), 1);
+}
+
+#[test]
+fn mismatching_checksum_fails_delayed_file() {
+    let mut server = server();
+    let client = ScriptedClient::new(Capabilities::all()).command_with_headers(
+        ProcessingType::Smudge,
+        "a",
+        &["can-delay=1", &checksum(b"hello")],
+        b"hellO",
+    );
+    let response = response(&mut server, client);
+    assert!(!response.contains("status=delayed"), "{}", response);
+    assert!(
+        response.ends_with("0011 status=error\\n\n0000\n"),
+        "{}",
+        response
+    );
+    assert!(server.summary().errors[0]
+        .reason
+        .contains("checksum mismatch"));
+}
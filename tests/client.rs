@@ -0,0 +1,46 @@
+mod common;
+
+use common::{Careless, Reversing};
+use git_filter_server::{
+    testing::spawn_server, Capabilities, ClientHandshake, GitFilterServer, Processor,
+};
+
+fn handshake<P: Processor + Send + 'static>(processor: P, offered: Capabilities) -> Capabilities {
+    let (handle, mut output, mut input) = spawn_server(GitFilterServer::new(processor), 1024);
+    let advertised = ClientHandshake::new(offered)
+        .perform(&mut input, &mut output)
+        .unwrap();
+    drop(output);
+    handle.join().1.unwrap();
+    advertised
+}
+
+#[test]
+fn handshake_negotiates_supported_capabilities() {
+    assert_eq!(
+        handshake(Careless, Capabilities::all()),
+        Capabilities::all()
+    );
+    assert_eq!(
+        handshake(Reversing::default(), Capabilities::all()),
+        Capabilities {
+            clean: false,
+            smudge: true,
+            delay: true,
+        }
+    );
+}
+
+#[test]
+fn handshake_only_advertises_offered_capabilities() {
+    let offered = Capabilities {
+        clean: true,
+        smudge: false,
+        delay: false,
+    };
+    assert_eq!(handshake(Careless, offered), offered);
+    assert_eq!(
+        handshake(Reversing::default(), offered),
+        Capabilities::default()
+    );
+}
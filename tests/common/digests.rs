@@ -0,0 +1,100 @@
+//! Small non-cryptographic digests, implementing the same traits as real hashes
+
+use digest::{
+    consts::{U4, U8},
+    FixedOutput, FixedOutputReset, HashMarker, Output, OutputSizeUser, Reset, Update,
+};
+
+/// FNV-1a, 64 bit
+#[derive(Clone)]
+pub struct Fnv64(u64);
+
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl HashMarker for Fnv64 {}
+
+impl OutputSizeUser for Fnv64 {
+    type OutputSize = U8;
+}
+
+impl Update for Fnv64 {
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+impl FixedOutput for Fnv64 {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        out.copy_from_slice(&self.0.to_be_bytes());
+    }
+}
+
+impl FixedOutputReset for Fnv64 {
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        out.copy_from_slice(&self.0.to_be_bytes());
+        Reset::reset(self);
+    }
+}
+
+impl Reset for Fnv64 {
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// CRC-32 (IEEE), bitwise
+#[derive(Clone)]
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self(!0)
+    }
+}
+
+impl HashMarker for Crc32 {}
+
+impl OutputSizeUser for Crc32 {
+    type OutputSize = U4;
+}
+
+impl Update for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 ^= u32::from(*byte);
+            for _ in 0..8 {
+                self.0 = if self.0 & 1 != 0 {
+                    (self.0 >> 1) ^ 0xedb8_8320
+                } else {
+                    self.0 >> 1
+                };
+            }
+        }
+    }
+}
+
+impl FixedOutput for Crc32 {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        out.copy_from_slice(&(!self.0).to_be_bytes());
+    }
+}
+
+impl FixedOutputReset for Crc32 {
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        out.copy_from_slice(&(!self.0).to_be_bytes());
+        Reset::reset(self);
+    }
+}
+
+impl Reset for Crc32 {
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,188 @@
+//! Processors shared by integration tests
+#![allow(dead_code)]
+
+pub mod digests;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Write},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use git_filter_server::{Pathname, ProcessingType, Processor};
+
+/// Smudge reverses content, delayed files are released once all files are scheduled
+#[derive(Default)]
+pub struct Reversing {
+    pub delay: bool,
+    /// Release single file on every `trickle`-th poll, instead of all at once
+    pub trickle: Option<usize>,
+    /// Processed right away, even if delay is enabled
+    pub immediate: BTreeSet<Pathname>,
+    /// Scheduled, but never released
+    pub lost: BTreeSet<Pathname>,
+    /// Files passed to `cancel_scheduled`
+    pub cancelled: Arc<Mutex<Vec<Pathname>>>,
+    scheduled: BTreeMap<Pathname, Vec<u8>>,
+    unreleased: Vec<Pathname>,
+    polls: usize,
+}
+
+impl Reversing {
+    pub fn delaying() -> Self {
+        Self {
+            delay: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn trickling(every: usize) -> Self {
+        Self {
+            delay: true,
+            trickle: Some(every),
+            ..Self::default()
+        }
+    }
+}
+
+fn reverse<R: Read>(input: &mut R) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    data.reverse();
+    Ok(data)
+}
+
+impl Processor for Reversing {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        _pathname: &Pathname,
+        _process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        output.write_all(&reverse(input)?)?;
+        Ok(())
+    }
+
+    fn schedule_process<R: Read>(
+        &mut self,
+        pathname: &Pathname,
+        _process_type: ProcessingType,
+        input: &mut R,
+    ) -> Result<()> {
+        self.scheduled.insert(pathname.clone(), reverse(input)?);
+        Ok(())
+    }
+
+    fn get_scheduled<W: Write>(
+        &mut self,
+        pathname: &Pathname,
+        _process_type: ProcessingType,
+        output: &mut W,
+    ) -> Result<()> {
+        let data = self
+            .scheduled
+            .remove(pathname)
+            .ok_or_else(|| anyhow::anyhow!("{} was not scheduled", pathname))?;
+        output.write_all(&data)?;
+        Ok(())
+    }
+
+    fn cancel_scheduled(&mut self, pathname: &Pathname, _process_type: ProcessingType) {
+        self.scheduled.remove(pathname);
+        self.cancelled.lock().unwrap().push(pathname.clone());
+    }
+
+    fn switch_to_wait(&mut self) {
+        self.unreleased = self
+            .scheduled
+            .keys()
+            .filter(|p| !self.lost.contains(p))
+            .cloned()
+            .collect();
+    }
+
+    fn get_available(&mut self) -> Result<Vec<Pathname>> {
+        self.polls += 1;
+        match self.trickle {
+            None => Ok(std::mem::take(&mut self.unreleased)),
+            Some(every) if self.polls.is_multiple_of(every) && !self.unreleased.is_empty() => {
+                Ok(vec![self.unreleased.remove(0)])
+            }
+            Some(_) => Ok(Vec::new()),
+        }
+    }
+
+    fn should_delay(&self, pathname: &Pathname, _process_type: ProcessingType) -> bool {
+        self.delay && !self.immediate.contains(pathname)
+    }
+
+    fn supports_processing(&self, process_type: ProcessingType) -> bool {
+        process_type == ProcessingType::Smudge
+    }
+}
+
+/// Ignores input, and writes `size` bytes of output for every file
+pub struct Generating {
+    pub size: usize,
+}
+
+impl Processor for Generating {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        _pathname: &Pathname,
+        _process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        std::io::copy(input, &mut std::io::sink())?;
+        let chunk = [b'x'; 4096];
+        let mut left = self.size;
+        while left > 0 {
+            let len = left.min(chunk.len());
+            output.write_all(&chunk[..len])?;
+            left -= len;
+        }
+        Ok(())
+    }
+
+    fn supports_processing(&self, _process_type: ProcessingType) -> bool {
+        true
+    }
+}
+
+/// Echoes input back, ignoring read errors, like a careless processor would
+pub struct Careless;
+
+impl Processor for Careless {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        _pathname: &Pathname,
+        _process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        let mut data = Vec::new();
+        let _ = input.read_to_end(&mut data);
+        output.write_all(&data)?;
+        Ok(())
+    }
+
+    fn supports_processing(&self, _process_type: ProcessingType) -> bool {
+        true
+    }
+}
+
+/// Run `f` on its own thread, panicking if it doesn't complete in time, i.e deadlocks
+pub fn within<T: Send + 'static>(seconds: u64, f: impl FnOnce() -> T + Send + 'static) -> T {
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(f());
+    });
+    match rx.recv_timeout(std::time::Duration::from_secs(seconds)) {
+        Ok(value) => value,
+        Err(std::sync::mpsc::RecvTimeoutError::Timeout) => panic!("deadlocked"),
+        Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => panic!("thread panicked"),
+    }
+}
@@ -0,0 +1,83 @@
+use std::io::{self, ErrorKind, Read};
+
+use git_filter_server::{BoundedDecompressor, CountingReader, DecompressionLimits};
+
+/// Run-length decoder, input is pairs of count and byte
+struct Rle<R> {
+    input: R,
+    byte: u8,
+    left: usize,
+}
+
+impl<R: Read> Read for Rle<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.left == 0 {
+            let mut pair = [0; 2];
+            match self.input.read_exact(&mut pair) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) => return Err(e),
+            }
+            self.left = pair[0] as usize;
+            self.byte = pair[1];
+        }
+        let len = buf.len().min(self.left);
+        buf[..len].fill(self.byte);
+        self.left -= len;
+        Ok(len)
+    }
+}
+
+fn decompress(compressed: &[u8], limits: DecompressionLimits) -> io::Result<Vec<u8>> {
+    let mut decoder = BoundedDecompressor::new(compressed, limits, |input: CountingReader<_>| {
+        Ok(Rle {
+            input,
+            byte: 0,
+            left: 0,
+        })
+    })?;
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output)?;
+    Ok(output)
+}
+
+fn limits() -> DecompressionLimits {
+    DecompressionLimits {
+        max_ratio: 100,
+        ratio_grace: 1024,
+        max_output_size: None,
+    }
+}
+
+#[test]
+fn input_within_limits_is_decompressed() {
+    let output = decompress(&[3, b'a', 2, b'b'], limits()).unwrap();
+    assert_eq!(output, b"aaabb");
+}
+
+#[test]
+fn high_ratio_input_is_rejected() {
+    // Every 2 bytes expand to 255, ratio is over 127
+    let bomb = [255, b'x'].repeat(10_000);
+    let e = decompress(&bomb, limits()).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert_eq!(e.to_string(), "max decompression ratio exceeded");
+}
+
+#[test]
+fn high_ratio_is_allowed_within_grace() {
+    let output = decompress(&[255, b'x', 255, b'y'], limits()).unwrap();
+    assert_eq!(output.len(), 510);
+}
+
+#[test]
+fn large_output_is_rejected() {
+    // Ratio is below 1, only absolute size is exceeded
+    let input = [1, b'x'].repeat(2000);
+    let limits = DecompressionLimits {
+        max_output_size: Some(1000),
+        ..limits()
+    };
+    let e = decompress(&input, limits).unwrap_err();
+    assert_eq!(e.to_string(), "max decompressed size exceeded");
+}
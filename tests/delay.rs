@@ -0,0 +1,214 @@
+mod common;
+
+use std::io::{self, Read};
+
+use anyhow::Result;
+use common::{within, Careless, Reversing};
+use git_filter_server::{
+    testing::{render_pkt_stream, Checkout, GitSimulator, ManualClock, ScriptedClient, Transcript},
+    Capabilities, GitFilterServer, Pathname, ProcessingType, Processor,
+};
+
+#[test]
+fn non_utf8_pathnames_are_delayed_distinctly() {
+    // Both would become "\u{fffd}1" after lossy conversion
+    let first = Pathname::from_bytes(&b"\xff1"[..]);
+    let second = Pathname::from_bytes(&b"\xfe1"[..]);
+    let checkout = GitSimulator::new()
+        .file(first.clone(), b"first")
+        .file(second.clone(), b"second")
+        .checkout(GitFilterServer::new(Reversing::delaying()))
+        .unwrap();
+    assert!(checkout.failed.is_empty(), "{:?}", checkout.failed);
+    assert_eq!(checkout.working_tree[&first], b"tsrif");
+    assert_eq!(checkout.working_tree[&second], b"dnoces");
+}
+
+fn trickle_checkout(batch_size: usize) -> Checkout<Reversing> {
+    let clock = ManualClock::new();
+    let server = GitFilterServer::new(Reversing::trickling(3))
+        .available_batch_size(batch_size)
+        .clock(clock);
+    GitSimulator::new()
+        .file("a", b"abc")
+        .file("b", b"def")
+        .file("c", b"ghi")
+        .checkout(server)
+        .unwrap()
+}
+
+#[test]
+fn trickling_blobs_are_listed_one_by_one() {
+    let checkout = trickle_checkout(1);
+    assert!(checkout.failed.is_empty(), "{:?}", checkout.failed);
+    assert_eq!(checkout.working_tree.len(), 3);
+    assert_eq!(checkout.working_tree[&Pathname::from("c")], b"ihg");
+    // Every blob in its own round, and the final empty one
+    assert_eq!(checkout.delay_rounds, 4);
+}
+
+#[test]
+fn trickling_blobs_are_batched() {
+    let checkout = trickle_checkout(2);
+    assert!(checkout.failed.is_empty(), "{:?}", checkout.failed);
+    assert_eq!(checkout.working_tree.len(), 3);
+    // Two blobs, then the last one without waiting for full batch, then empty list
+    assert_eq!(checkout.delay_rounds, 3);
+}
+
+#[test]
+fn listing_without_delayed_files_doesnt_poll_processor() {
+    // Doesn't implement delaying, polling it would panic
+    let mut server = GitFilterServer::new(Careless);
+    let client = ScriptedClient::new(Capabilities::all())
+        .command(ProcessingType::Clean, "a", b"abc")
+        .list_available_blobs();
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    assert!(render_pkt_stream(&transcript.server).ends_with("0000\n0013 status=success\\n\n0000\n"));
+}
+
+fn file_responses(transcript: &Transcript) -> Vec<String> {
+    let response = render_pkt_stream(&transcript.server);
+    // Skip handshake
+    let response = response.splitn(3, "0000\n").nth(2).unwrap();
+    response
+        .split_inclusive("0000\n")
+        .map(str::to_owned)
+        .collect()
+}
+
+#[test]
+fn delayed_file_requested_before_listing_is_failed() {
+    let processor = Reversing::delaying();
+    let cancelled = processor.cancelled.clone();
+    let mut server = GitFilterServer::new(processor);
+    let client = ScriptedClient::new(Capabilities::all())
+        .delayable_command(ProcessingType::Smudge, "a", b"abc")
+        .delayable_command(ProcessingType::Smudge, "b", b"def")
+        .delayed_request(ProcessingType::Smudge, "a")
+        .list_available_blobs()
+        .delayed_request(ProcessingType::Smudge, "b")
+        .list_available_blobs();
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    assert_eq!(
+        file_responses(&transcript),
+        [
+            "0013 status=delayed\\n\n0000\n",
+            "0013 status=delayed\\n\n0000\n",
+            "0011 status=error\\n\n0000\n",
+            "000f pathname=b\\n\n0000\n",
+            "0013 status=success\\n\n0000\n",
+            "0013 status=success\\n\n0000\n",
+            "0007 fed\n0000\n",
+            "0000\n",
+            "0000\n",
+            "0013 status=success\\n\n0000\n",
+        ]
+    );
+    assert_eq!(*cancelled.lock().unwrap(), [Pathname::from("a")]);
+    assert_eq!(server.summary().errors.len(), 1);
+}
+
+#[test]
+fn unknown_file_in_wait_state_is_processed_right_away() {
+    let mut server = GitFilterServer::new(Reversing::delaying());
+    let client = ScriptedClient::new(Capabilities::all())
+        .delayable_command(ProcessingType::Smudge, "a", b"abc")
+        .list_available_blobs()
+        .delayed_request(ProcessingType::Smudge, "a")
+        .command(ProcessingType::Smudge, "b", b"def")
+        .list_available_blobs();
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    let responses = file_responses(&transcript);
+    assert_eq!(
+        responses[6..8],
+        ["0013 status=success\\n\n0000\n", "0007 fed\n0000\n"]
+    );
+    assert!(server.summary().errors.is_empty());
+}
+
+#[test]
+fn pending_files_are_cancelled_after_disconnect() {
+    let mut processor = Reversing::delaying();
+    processor.immediate.insert(Pathname::from("immediate"));
+    let cancelled = processor.cancelled.clone();
+    let mut server = GitFilterServer::new(processor);
+    // Git is killed before asking for delayed files
+    let client = ScriptedClient::new(Capabilities::all())
+        .delayable_command(ProcessingType::Smudge, "a", b"abc")
+        .delayable_command(ProcessingType::Smudge, "immediate", b"def")
+        .delayable_command(ProcessingType::Smudge, "b", b"ghi")
+        .delayable_command(ProcessingType::Smudge, "c", b"jkl")
+        .list_available_blobs()
+        .delayed_request(ProcessingType::Smudge, "a");
+    Transcript::record(&mut server, client).unwrap();
+    let mut cancelled = cancelled.lock().unwrap().clone();
+    cancelled.sort();
+    assert_eq!(cancelled, [Pathname::from("b"), Pathname::from("c")]);
+}
+
+#[test]
+fn files_without_can_delay_are_processed_right_away() {
+    // Delay is negotiated, but git never marks any file as delayable
+    let mut server = GitFilterServer::new(Reversing::delaying());
+    let client = ScriptedClient::new(Capabilities::all())
+        .command(ProcessingType::Smudge, "a", b"abc")
+        .command(ProcessingType::Smudge, "b", b"def");
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    assert_eq!(
+        file_responses(&transcript),
+        [
+            "0013 status=success\\n\n0000\n",
+            "0007 cba\n0000\n",
+            "0000\n",
+            "0013 status=success\\n\n0000\n",
+            "0007 fed\n0000\n",
+            "0000\n",
+        ]
+    );
+    assert!(server.summary().errors.is_empty());
+}
+
+/// Delays every file and never releases it, but keeps reporting file, which was never delayed
+struct Haunted;
+
+impl Processor for Haunted {
+    fn schedule_process<R: Read>(
+        &mut self,
+        _pathname: &Pathname,
+        _process_type: ProcessingType,
+        input: &mut R,
+    ) -> Result<()> {
+        io::copy(input, &mut io::sink())?;
+        Ok(())
+    }
+
+    fn get_available(&mut self) -> Result<Vec<Pathname>> {
+        Ok(vec![Pathname::from("ghost")])
+    }
+
+    fn should_delay(&self, _pathname: &Pathname, _process_type: ProcessingType) -> bool {
+        true
+    }
+
+    fn supports_processing(&self, process_type: ProcessingType) -> bool {
+        process_type == ProcessingType::Smudge
+    }
+}
+
+#[test]
+fn unknown_available_files_dont_count_as_progress() {
+    let clock = ManualClock::new();
+    let server = GitFilterServer::new(Haunted)
+        .max_empty_polls(3)
+        .clock(clock.clone());
+    let checkout = within(10, move || {
+        GitSimulator::new()
+            .file("a", b"abc")
+            .checkout(server)
+            .unwrap()
+    });
+    assert_eq!(checkout.failed, [Pathname::from("a")]);
+    // Server slept between polls, instead of spinning
+    assert_eq!(clock.elapsed(), 2 * std::time::Duration::from_millis(10));
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use std::io::{self, Read};
+
+use common::digests::{Crc32, Fnv64};
+use digest::Digest;
+use git_filter_server::MultiHashingReader;
+
+/// Returns at most 7 bytes per read, so digests are fed in several updates
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.0.len()).min(7);
+        buf[..len].copy_from_slice(&self.0[..len]);
+        self.0 = &self.0[len..];
+        Ok(len)
+    }
+}
+
+#[test]
+fn reference_digests_are_correct() {
+    assert_eq!(
+        Fnv64::digest(b"a")[..],
+        0xaf63_dc4c_8601_ec8cu64.to_be_bytes()
+    );
+    assert_eq!(
+        Crc32::digest(b"123456789")[..],
+        0xcbf4_3926u32.to_be_bytes()
+    );
+}
+
+#[test]
+fn every_digest_matches_single_algorithm_reference() {
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i * 31 % 256) as u8).collect();
+    let mut reader = MultiHashingReader::new(Trickle(&data))
+        .with_digest(Fnv64::default())
+        .with_digest(Crc32::default());
+    let mut consumed = Vec::new();
+    reader.read_to_end(&mut consumed).unwrap();
+    assert_eq!(consumed, data);
+
+    let (digests, read_bytes) = reader.finalize();
+    assert_eq!(read_bytes, data.len() as u64);
+    assert_eq!(digests[0][..], Fnv64::digest(&data)[..]);
+    assert_eq!(digests[1][..], Crc32::digest(&data)[..]);
+}
+
+#[test]
+fn digests_of_empty_input() {
+    let reader = MultiHashingReader::new(io::empty())
+        .with_digest(Fnv64::default())
+        .with_digest(Crc32::default());
+    let (digests, read_bytes) = reader.finalize();
+    assert_eq!(read_bytes, 0);
+    assert_eq!(digests[0][..], Fnv64::digest(b"")[..]);
+    assert_eq!(digests[1][..], Crc32::digest(b"")[..]);
+}
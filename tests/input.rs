@@ -0,0 +1,147 @@
+mod common;
+
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use common::{Careless, Reversing};
+use git_filter_server::{
+    testing::{render_pkt_stream, ScriptedClient, Transcript},
+    Capabilities, GitFilterServer, Pathname, ProcessingType, Processor, UnreadInput,
+};
+
+#[test]
+fn truncated_input_fails_file_even_if_processor_ignores_it() {
+    let mut server = GitFilterServer::new(Careless).max_input_size(4);
+    let client = ScriptedClient::new(Capabilities::all())
+        .command(ProcessingType::Clean, "large", b"hello world")
+        .command(ProcessingType::Clean, "small", b"hi");
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    let response = render_pkt_stream(&transcript.server);
+    assert!(!response.contains("status=success"), "{}", response);
+    assert!(
+        response.ends_with("0011 status=error\\n\n0000\n"),
+        "{}",
+        response
+    );
+    assert_eq!(server.summary().errors.len(), 1);
+}
+
+#[test]
+fn many_tiny_records_fail_file_under_size_limit() {
+    let mut server = GitFilterServer::new(Reversing::default())
+        .max_input_size(1024)
+        .max_records_per_file(16);
+    let client = ScriptedClient::new(Capabilities::all()).command_in_records(
+        ProcessingType::Smudge,
+        "tiny",
+        &[b'x'; 100],
+        1,
+    );
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    let response = render_pkt_stream(&transcript.server);
+    assert!(
+        response.ends_with("0011 status=error\\n\n0000\n"),
+        "{}",
+        response
+    );
+    let errors = &server.summary().errors;
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].reason, "max input records exceeded");
+}
+
+#[test]
+fn records_up_to_limit_are_accepted() {
+    let mut server = GitFilterServer::new(Reversing::default()).max_records_per_file(16);
+    let client = ScriptedClient::new(Capabilities::all()).command_in_records(
+        ProcessingType::Smudge,
+        "tiny",
+        &[b'x'; 16],
+        1,
+    );
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    let response = render_pkt_stream(&transcript.server);
+    assert!(!response.contains("status=error"), "{}", response);
+    assert!(server.summary().errors.is_empty());
+}
+
+#[test]
+fn command_which_wasnt_negotiated_fails_file() {
+    let mut server = GitFilterServer::new(Careless);
+    let client = ScriptedClient::new(Capabilities {
+        clean: false,
+        smudge: true,
+        delay: false,
+    })
+    .command(ProcessingType::Clean, "a", b"abc")
+    .command(ProcessingType::Smudge, "b", b"def");
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    let response = render_pkt_stream(&transcript.server);
+    assert!(
+        response.ends_with(concat!(
+            "0011 status=error\\n\n0000\n",
+            "0013 status=success\\n\n0000\n",
+            "0007 def\n0000\n",
+            "0000\n",
+        )),
+        "{}",
+        response
+    );
+    let errors = &server.summary().errors;
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].pathname, Pathname::from("a"));
+    assert_eq!(errors[0].reason, "clean capability wasn't negotiated");
+}
+
+/// Responds with pathname, without looking at input
+struct Ignoring;
+
+impl Processor for Ignoring {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        pathname: &Pathname,
+        _process_type: ProcessingType,
+        _input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        output.write_all(pathname.as_bytes())?;
+        Ok(())
+    }
+
+    fn supports_processing(&self, _process_type: ProcessingType) -> bool {
+        true
+    }
+}
+
+fn ignored_input_client() -> ScriptedClient {
+    // Spans several records
+    ScriptedClient::new(Capabilities::all())
+        .command(ProcessingType::Clean, "large", &[b'x'; 100 * 1024])
+        .command(ProcessingType::Clean, "small", b"abc")
+}
+
+#[test]
+fn unread_input_is_drained() {
+    let mut server = GitFilterServer::new(Ignoring);
+    let transcript = Transcript::record(&mut server, ignored_input_client()).unwrap();
+    let response = render_pkt_stream(&transcript.server);
+    assert!(
+        response.ends_with(concat!(
+            "0013 status=success\\n\n0000\n",
+            "0009 large\n0000\n",
+            "0000\n",
+            "0013 status=success\\n\n0000\n",
+            "0009 small\n0000\n",
+            "0000\n",
+        )),
+        "{}",
+        response
+    );
+    assert!(server.summary().errors.is_empty());
+}
+
+#[test]
+#[should_panic(expected = "processor didn't consume whole input, 102400 bytes left")]
+fn unread_input_panics_in_strict_mode() {
+    let mut server = GitFilterServer::new(Ignoring).unread_input(UnreadInput::Panic);
+    let _ = Transcript::record(&mut server, ignored_input_client());
+}
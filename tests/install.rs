@@ -0,0 +1,104 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use git_filter_server::{
+    ensure_gitattributes, ensure_gitattributes_many, parse_filter_config, read_filter_config,
+};
+
+/// Fresh directory, unique per test
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "git-filter-server-install-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Names of files in directory, to check no temporary file is left behind
+fn files(dir: &Path) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn existing_entry_is_recognized() {
+    let dir = temp_dir("existing");
+    let path = dir.join(".gitattributes");
+    let content = "# comment\n*.bin\tdiff  filter=crypt -text\n";
+    fs::write(&path, content).unwrap();
+    assert!(!ensure_gitattributes(&path, "*.bin", "crypt").unwrap());
+    assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    // Same pattern with other filter is a different entry
+    assert!(ensure_gitattributes(&path, "*.bin", "other").unwrap());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        format!("{}*.bin filter=other\n", content)
+    );
+    assert_eq!(files(&dir), [".gitattributes"]);
+}
+
+#[test]
+fn duplicate_entries_are_added_once() {
+    let dir = temp_dir("duplicates");
+    let path = dir.join(".gitattributes");
+    let entries = [("*.a", "x"), ("*.b", "x"), ("*.a", "x")];
+    assert!(ensure_gitattributes_many(&path, &entries).unwrap());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "*.a filter=x\n*.b filter=x\n"
+    );
+    assert!(!ensure_gitattributes_many(&path, &entries).unwrap());
+    assert_eq!(files(&dir), [".gitattributes"]);
+}
+
+#[test]
+fn missing_trailing_newline_is_added() {
+    let dir = temp_dir("newline");
+    let path = dir.join(".gitattributes");
+    fs::write(&path, "*.c filter=y").unwrap();
+    assert!(ensure_gitattributes(&path, "*.d", "z").unwrap());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "*.c filter=y\n*.d filter=z\n"
+    );
+}
+
+#[test]
+fn filter_config_is_parsed() {
+    let output =
+        b"filter.crypt.required\0filter.crypt.key\nline 1\nline 2\0filter.crypt.key\nlast\0";
+    let config = parse_filter_config("crypt", output).unwrap();
+    assert_eq!(config.len(), 2);
+    // Valueless key is boolean true
+    assert_eq!(config["required"], "true");
+    // Value may contain newlines, and last one wins
+    assert_eq!(config["key"], "last");
+    let config = parse_filter_config("crypt", b"filter.crypt.key\nline 1\nline 2\0").unwrap();
+    assert_eq!(config["key"], "line 1\nline 2");
+}
+
+#[test]
+fn unexpected_filter_config_key_is_rejected() {
+    let e = parse_filter_config("crypt", b"filter.other.key\nvalue\0").unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "unexpected git config key: \"filter.other.key\""
+    );
+    assert!(parse_filter_config("crypt", b"filter.crypt.\nvalue\0").is_err());
+}
+
+#[test]
+fn missing_filter_config_is_empty() {
+    // Git exits with 1, when no key matches
+    let config = read_filter_config("git-filter-server-test-missing").unwrap();
+    assert!(config.is_empty());
+}
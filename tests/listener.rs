@@ -0,0 +1,105 @@
+mod common;
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use common::Careless;
+use git_filter_server::{
+    testing::{ScriptedClient, Transcript},
+    AtCapacity, Capabilities, GitFilterServer, TcpFilterServer,
+};
+
+/// Start server with single session slot, returns its address
+fn serve(at_capacity: AtCapacity) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = TcpFilterServer::new(|| GitFilterServer::new(Careless))
+        .max_sessions(1)
+        .at_capacity(at_capacity);
+    thread::spawn(move || server.serve(listener));
+    addr
+}
+
+fn handshake_response() -> Vec<u8> {
+    Transcript::record(
+        &mut GitFilterServer::new(Careless),
+        ScriptedClient::new(Capabilities::all()),
+    )
+    .unwrap()
+    .server
+}
+
+/// Connect and send handshake, session stays open until stream is dropped
+fn connect(addr: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(&ScriptedClient::new(Capabilities::all()).into_bytes())
+        .unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    stream
+}
+
+fn assert_served(stream: &mut TcpStream) {
+    let mut response = vec![0; handshake_response().len()];
+    stream.read_exact(&mut response).unwrap();
+    assert_eq!(response, handshake_response());
+}
+
+#[test]
+fn connection_over_limit_waits_for_free_slot() {
+    let addr = serve(AtCapacity::Queue);
+    let mut first = connect(&addr);
+    assert_served(&mut first);
+
+    let mut second = connect(&addr);
+    second
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let e = second.read(&mut [0; 1]).unwrap_err();
+    assert!(
+        matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+        "{}",
+        e
+    );
+
+    drop(first);
+    second
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    assert_served(&mut second);
+}
+
+#[test]
+fn connection_over_limit_is_rejected() {
+    let addr = serve(AtCapacity::Reject);
+    let mut first = connect(&addr);
+    assert_served(&mut first);
+
+    let mut second = connect(&addr);
+    match second.read(&mut [0; 1]) {
+        Ok(read) => assert_eq!(read, 0),
+        Err(e) => assert_eq!(e.kind(), ErrorKind::ConnectionReset),
+    }
+
+    // Slot is freed once server notices end of session
+    drop(first);
+    let expected = handshake_response();
+    for _ in 0..100 {
+        let mut third = connect(&addr);
+        third.shutdown(Shutdown::Write).unwrap();
+        let mut response = Vec::new();
+        let _ = third.read_to_end(&mut response);
+        if !response.is_empty() {
+            assert!(response.starts_with(&expected));
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("slot was never freed");
+}
@@ -0,0 +1,46 @@
+use git_filter_server::{read_available_blobs, write_available_blobs, Pathname};
+
+#[test]
+fn listing_round_trips() {
+    let pathnames = vec![
+        Pathname::from("a"),
+        Pathname::from("dir/b c"),
+        Pathname::from_bytes(&b"\xff\xfe"[..]),
+    ];
+    let mut buf = Vec::new();
+    write_available_blobs(&mut buf, &pathnames).unwrap();
+    assert_eq!(read_available_blobs(&mut &buf[..]).unwrap(), pathnames);
+}
+
+#[test]
+fn empty_listing_round_trips() {
+    let mut buf = Vec::new();
+    write_available_blobs(&mut buf, Vec::<Pathname>::new()).unwrap();
+    assert_eq!(buf, b"00000013status=success\n0000");
+    assert_eq!(read_available_blobs(&mut &buf[..]).unwrap(), vec![]);
+}
+
+#[test]
+fn listings_are_read_one_round_at_a_time() {
+    let rounds = [vec!["a", "b"], vec!["c"], vec![]];
+    let mut buf = Vec::new();
+    for round in &rounds {
+        write_available_blobs(&mut buf, round).unwrap();
+    }
+    let mut input = &buf[..];
+    for round in &rounds {
+        let expected = round.iter().map(|&p| Pathname::from(p)).collect::<Vec<_>>();
+        assert_eq!(read_available_blobs(&mut input).unwrap(), expected);
+    }
+    assert!(input.is_empty());
+}
+
+#[test]
+fn listing_with_error_status_fails() {
+    let mut input = &b"00000011status=error\n0000"[..];
+    let e = read_available_blobs(&mut input).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "listing available blobs failed with status error"
+    );
+}
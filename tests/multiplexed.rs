@@ -0,0 +1,57 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::Careless;
+use git_filter_server::{
+    testing::ScriptedClient, Capabilities, GitFilterServer, MetricsSink, ProcessingType,
+    SessionSummary,
+};
+
+#[derive(Clone, Default)]
+struct FileCounts(Arc<Mutex<Vec<u64>>>);
+
+impl MetricsSink for FileCounts {
+    fn record(&mut self, summary: &SessionSummary) {
+        self.0.lock().unwrap().push(summary.files);
+    }
+}
+
+fn session(files: usize) -> Vec<u8> {
+    let mut client = ScriptedClient::new(Capabilities::all());
+    for i in 0..files {
+        client = client.command(ProcessingType::Clean, format!("file{}", i), b"data");
+    }
+    let mut data = client.into_bytes();
+    // End of session marker
+    data.extend_from_slice(b"0000");
+    data
+}
+
+#[test]
+fn closed_stream_after_session_is_not_a_session() {
+    let counts = FileCounts::default();
+    let mut server = GitFilterServer::new(Careless).metrics_sink(counts.clone());
+    let input = session(1);
+    let sessions = server
+        .communicate_multiplexed(&mut input.as_slice(), &mut Vec::new())
+        .unwrap();
+    assert_eq!(sessions, 1);
+    assert_eq!(*counts.0.lock().unwrap(), [1]);
+    assert_eq!(server.summary().files, 1);
+}
+
+#[test]
+fn consecutive_sessions_are_counted() {
+    let counts = FileCounts::default();
+    let mut server = GitFilterServer::new(Careless).metrics_sink(counts.clone());
+    let mut input = session(2);
+    input.extend(session(1));
+    // Last session may end by closing stream
+    input.extend(ScriptedClient::new(Capabilities::all()).into_bytes());
+    let sessions = server
+        .communicate_multiplexed(&mut input.as_slice(), &mut Vec::new())
+        .unwrap();
+    assert_eq!(sessions, 3);
+    assert_eq!(*counts.0.lock().unwrap(), [2, 1, 0]);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    time::Duration,
+};
+
+use anyhow::Result;
+
+use common::Generating;
+use git_filter_server::{
+    testing::{pipe, render_pkt_stream, ScriptedClient, Transcript},
+    Capabilities, GitFilterServer, Pathname, ProcessingType, Processor, TimeoutWriter,
+};
+
+#[test]
+fn stuck_output_fails_session_with_timeout() {
+    let (writer, _reader) = pipe(1024);
+    let mut output = TimeoutWriter::new(writer, Duration::from_millis(50)).unwrap();
+    let client = ScriptedClient::new(Capabilities::all())
+        .command(ProcessingType::Clean, "big", b"")
+        .into_bytes();
+    let mut server = GitFilterServer::new(Generating { size: 1024 * 1024 });
+    let e = server
+        .communicate(&mut client.as_slice(), &mut output)
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+}
+
+#[test]
+fn empty_output_is_sent_as_status_and_three_flushes() {
+    let handshake = Transcript::record(
+        &mut GitFilterServer::new(Generating { size: 0 }),
+        ScriptedClient::new(Capabilities::all()),
+    )
+    .unwrap()
+    .server;
+    let mut server = GitFilterServer::new(Generating { size: 0 });
+    let client =
+        ScriptedClient::new(Capabilities::all()).command(ProcessingType::Smudge, "empty", b"abc");
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    let response = transcript.server.strip_prefix(&handshake[..]).unwrap();
+    // Status list, empty content, and empty list keeping status
+    assert_eq!(response, b"0013status=success\n000000000000");
+    assert!(server.summary().errors.is_empty());
+}
+
+/// Writes `size` bytes, after promising `hint` bytes
+struct Hinted {
+    hint: u64,
+    size: usize,
+}
+
+impl Processor for Hinted {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        _pathname: &Pathname,
+        _process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        std::io::copy(input, &mut std::io::sink())?;
+        output.write_all(&vec![b'x'; self.size])?;
+        Ok(())
+    }
+
+    fn output_size_hint(&self, _pathname: &Pathname, _process_type: ProcessingType) -> Option<u64> {
+        Some(self.hint)
+    }
+
+    fn supports_processing(&self, _process_type: ProcessingType) -> bool {
+        true
+    }
+}
+
+fn hinted_response(size: usize) -> String {
+    let mut server = GitFilterServer::new(Hinted { hint: 100, size }).strict_size_hint(10);
+    let client = ScriptedClient::new(Capabilities::all()).command(ProcessingType::Smudge, "a", b"");
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    render_pkt_stream(&transcript.server)
+}
+
+#[test]
+fn output_within_hint_tolerance_succeeds() {
+    let response = hinted_response(110);
+    assert!(response.ends_with("0000\n0000\n"), "{}", response);
+    assert!(response.contains("0072 xxx"), "{}", response);
+    assert!(!response.contains("status=error"), "{}", response);
+}
+
+#[test]
+fn output_over_hint_tolerance_fails() {
+    let response = hinted_response(111);
+    assert!(
+        response.ends_with("0011 status=error\\n\n0000\n"),
+        "{}",
+        response
+    );
+}
@@ -0,0 +1,110 @@
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use git_filter_server::{
+    testing::{render_pkt_stream, ScriptedClient, Transcript},
+    Capabilities, GitFilterServer, Pathname, ProcessingType, Processor,
+};
+
+/// Echoes input, but panics on files named `panic`, after writing `output` bytes
+struct Panicking {
+    read_input: bool,
+    output: usize,
+}
+
+impl Processor for Panicking {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        pathname: &Pathname,
+        _process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        if pathname.as_bytes() != b"panic" {
+            std::io::copy(input, output)?;
+            return Ok(());
+        }
+        if self.read_input {
+            std::io::copy(input, &mut std::io::sink())?;
+        }
+        output.write_all(&vec![b'x'; self.output])?;
+        panic!("processor bug");
+    }
+
+    fn supports_processing(&self, _process_type: ProcessingType) -> bool {
+        true
+    }
+}
+
+/// Server records after handshake, with payload of filler records replaced by its size
+fn file_records(processor: Panicking) -> (Vec<String>, GitFilterServer<Panicking>) {
+    let mut server = GitFilterServer::new(processor).catch_panics(true);
+    // Input spans several records, so it has to be drained after panic
+    let input = vec![b'i'; 100 * 1024];
+    let client = ScriptedClient::new(Capabilities::all())
+        .command(ProcessingType::Clean, "panic", &input)
+        .command(ProcessingType::Clean, "next", b"abc");
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    let records = render_pkt_stream(&transcript.server)
+        .lines()
+        .skip(7)
+        .map(|line| match line.split_once(' ') {
+            Some((len, payload)) if payload.bytes().all(|b| b == b'x') => {
+                format!("{} <{} bytes>", len, payload.len())
+            }
+            _ => line.to_owned(),
+        })
+        .collect();
+    (records, server)
+}
+
+#[test]
+fn panic_before_output_fails_file() {
+    let (records, server) = file_records(Panicking {
+        read_input: false,
+        output: 0,
+    });
+    assert_eq!(
+        records,
+        [
+            "0011 status=error\\n",
+            "0000",
+            "0013 status=success\\n",
+            "0000",
+            "0007 abc",
+            "0000",
+            "0000",
+        ]
+    );
+    let errors = &server.summary().errors;
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].reason, "processor panicked: processor bug");
+}
+
+#[test]
+fn panic_after_output_fails_file_after_content() {
+    // More than fits in single record, so status and content are already sent,
+    // git discards content once it gets error status after it
+    let (records, server) = file_records(Panicking {
+        read_input: true,
+        output: 100 * 1024,
+    });
+    assert_eq!(
+        records,
+        [
+            "0013 status=success\\n",
+            "0000",
+            "fff0 <65516 bytes>",
+            "9018 <36884 bytes>",
+            "0000",
+            "0011 status=error\\n",
+            "0000",
+            "0013 status=success\\n",
+            "0000",
+            "0007 abc",
+            "0000",
+            "0000",
+        ]
+    );
+    assert_eq!(server.summary().errors.len(), 1);
+}
@@ -0,0 +1,182 @@
+use std::{
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{ensure, Result};
+use git_filter_server::{
+    Pathname, Pipeline, ProcessingType, Processor, SplitProcessor, Transform, Uploader,
+    UploadingCleanProcessor,
+};
+
+fn run<P: Processor>(processor: &mut P, process_type: ProcessingType, data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    processor
+        .process(
+            &Pathname::from("file"),
+            process_type,
+            &mut &data[..],
+            &mut output,
+        )
+        .unwrap();
+    output
+}
+
+/// Adds header on clean, and strips it on smudge
+struct Header(&'static [u8]);
+
+impl Transform for Header {
+    fn apply(
+        &mut self,
+        process_type: ProcessingType,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<()> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        match process_type {
+            ProcessingType::Clean => {
+                output.write_all(self.0)?;
+                output.write_all(&data)?;
+            }
+            ProcessingType::Smudge => {
+                ensure!(data.starts_with(self.0), "missing header");
+                output.write_all(&data[self.0.len()..])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Xors every byte with key, which is its own inverse
+struct Xor(u8);
+
+impl Transform for Xor {
+    fn apply(
+        &mut self,
+        _process_type: ProcessingType,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<()> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        data.iter_mut().for_each(|b| *b ^= self.0);
+        output.write_all(&data)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn pipeline_smudge_is_inverse_of_clean() {
+    let mut pipeline = Pipeline::new().stage(Header(b"v1:")).stage(Xor(0x20));
+    let data = b"Hello, World";
+    let cleaned = run(&mut pipeline, ProcessingType::Clean, data);
+    // Stages are applied in order on clean, so header is xored too
+    assert_eq!(cleaned, b"V\x11\x1ahELLO\x0c\0wORLD");
+    assert_eq!(run(&mut pipeline, ProcessingType::Smudge, &cleaned), data);
+}
+
+#[test]
+fn pipeline_stage_failure_names_stage() {
+    let mut pipeline = Pipeline::new().stage(Xor(0x20)).stage(Header(b"v1:"));
+    let e = pipeline
+        .process(
+            &Pathname::from("file"),
+            ProcessingType::Smudge,
+            &mut &b"data"[..],
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        format!("{:#}", e),
+        "pipeline stage 1 failed: missing header"
+    );
+}
+
+#[test]
+fn split_routes_clean_and_smudge_to_own_processor() {
+    let mut split = SplitProcessor::new(
+        Pipeline::new().stage(Header(b"v1:")),
+        Pipeline::new().stage(Xor(0x20)),
+    );
+    assert_eq!(run(&mut split, ProcessingType::Clean, b"data"), b"v1:data");
+    assert_eq!(run(&mut split, ProcessingType::Smudge, b"data"), b"DATA");
+    // Header is only known to clean side, so smudge doesn't strip it
+    assert_eq!(
+        run(&mut split, ProcessingType::Smudge, b"v1:"),
+        b"V\x11\x1a"
+    );
+}
+
+/// Upload, which breaks after accepting `left` bytes
+struct FlakyUpload {
+    left: usize,
+}
+
+impl Write for FlakyUpload {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.left == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "remote hung up",
+            ));
+        }
+        let len = buf.len().min(self.left);
+        self.left -= len;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct FlakyUploader {
+    finished: Arc<Mutex<Vec<Pathname>>>,
+    aborted: Arc<Mutex<Vec<Pathname>>>,
+}
+
+impl Uploader for FlakyUploader {
+    type Upload = FlakyUpload;
+
+    fn begin(&mut self, _pathname: &Pathname) -> Result<FlakyUpload> {
+        Ok(FlakyUpload { left: 10 })
+    }
+
+    fn finish(&mut self, pathname: &Pathname, _upload: FlakyUpload, size: u64) -> Result<Vec<u8>> {
+        self.finished.lock().unwrap().push(pathname.clone());
+        Ok(format!("pointer {}", size).into_bytes())
+    }
+
+    fn abort(&mut self, pathname: &Pathname, _upload: FlakyUpload) {
+        self.aborted.lock().unwrap().push(pathname.clone());
+    }
+}
+
+#[test]
+fn upload_failing_midway_is_aborted_without_output() {
+    let uploader = FlakyUploader::default();
+    let finished = uploader.finished.clone();
+    let aborted = uploader.aborted.clone();
+    let mut processor = UploadingCleanProcessor::new(uploader);
+
+    assert_eq!(
+        run(&mut processor, ProcessingType::Clean, b"small"),
+        b"pointer 5"
+    );
+
+    let mut output = Vec::new();
+    let e = processor
+        .process(
+            &Pathname::from("large"),
+            ProcessingType::Clean,
+            &mut &[0; 100][..],
+            &mut output,
+        )
+        .unwrap_err();
+    assert_eq!(format!("{:#}", e), "upload failed: remote hung up");
+    assert!(output.is_empty());
+    assert_eq!(*finished.lock().unwrap(), [Pathname::from("file")]);
+    assert_eq!(*aborted.lock().unwrap(), [Pathname::from("large")]);
+}
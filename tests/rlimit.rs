@@ -0,0 +1,58 @@
+//! Limits are applied to the whole process, so they are checked in re-executed child
+#![cfg(target_os = "linux")]
+
+use std::{fs, process::Command, time::Duration};
+
+use git_filter_server::ResourceLimits;
+
+const CHILD_ENV: &str = "GIT_FILTER_SERVER_RLIMIT_CHILD";
+
+/// Soft and hard limit from /proc/self/limits
+fn limit(name: &str) -> (String, String) {
+    let limits = fs::read_to_string("/proc/self/limits").unwrap();
+    let line = limits
+        .lines()
+        .find(|line| line.starts_with(name))
+        .unwrap_or_else(|| panic!("{} is not listed", name));
+    let mut values = line[name.len()..].split_whitespace();
+    (
+        values.next().unwrap().to_owned(),
+        values.next().unwrap().to_owned(),
+    )
+}
+
+#[test]
+fn child_applies_limits() {
+    if std::env::var_os(CHILD_ENV).is_none() {
+        return;
+    }
+    ResourceLimits {
+        max_memory: Some(4 << 30),
+        max_cpu_time: Some(Duration::from_millis(3_599_500)),
+    }
+    .apply()
+    .unwrap();
+    let memory = (4u64 << 30).to_string();
+    assert_eq!(limit("Max address space"), (memory.clone(), memory));
+    // Rounded up to whole seconds
+    assert_eq!(
+        limit("Max cpu time"),
+        ("3600".to_owned(), "3600".to_owned())
+    );
+}
+
+#[test]
+fn limits_are_applied_to_process() {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "child_applies_limits", "--test-threads=1"])
+        .env(CHILD_ENV, "1")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success() && stdout.contains("1 passed"),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
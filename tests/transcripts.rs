@@ -0,0 +1,91 @@
+//! Exact wire transcripts, run with `GIT_FILTER_SERVER_BLESS=1` to update fixtures
+mod common;
+
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use common::Reversing;
+use git_filter_server::{
+    testing::{ScriptedClient, Transcript},
+    Capabilities, GitFilterServer, Pathname, ProcessingType, Processor,
+};
+
+fn assert_transcript(name: &str, processor: Reversing, client: ScriptedClient) {
+    assert_server_transcript(name, GitFilterServer::new(processor), client);
+}
+
+fn assert_server_transcript<P: Processor>(
+    name: &str,
+    mut server: GitFilterServer<P>,
+    client: ScriptedClient,
+) {
+    let transcript = Transcript::record(&mut server, client).unwrap();
+    transcript.assert_golden(format!(
+        "{}/tests/transcripts/{}.txt",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    ));
+}
+
+#[test]
+fn handshake() {
+    let client = ScriptedClient::new(Capabilities::all());
+    assert_transcript("handshake", Reversing::default(), client);
+}
+
+#[test]
+fn smudge() {
+    let client = ScriptedClient::new(Capabilities::all())
+        .command(ProcessingType::Smudge, "a.txt", b"hello")
+        .command(ProcessingType::Smudge, "empty.txt", b"");
+    assert_transcript("smudge", Reversing::default(), client);
+}
+
+#[test]
+fn delayed_smudge() {
+    let client = ScriptedClient::new(Capabilities::all())
+        .delayable_command(ProcessingType::Smudge, "a.txt", b"hello")
+        .delayable_command(ProcessingType::Smudge, "b.txt", b"world")
+        .list_available_blobs()
+        .delayed_request(ProcessingType::Smudge, "a.txt")
+        .delayed_request(ProcessingType::Smudge, "b.txt")
+        .list_available_blobs();
+    assert_transcript("delayed_smudge", Reversing::delaying(), client);
+}
+
+/// Echoes input, writing and flushing it in chunks of 3 bytes
+struct Flushing;
+
+impl Processor for Flushing {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        _pathname: &Pathname,
+        _process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<()> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        for chunk in data.chunks(3) {
+            output.write_all(chunk)?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    fn supports_processing(&self, _process_type: ProcessingType) -> bool {
+        true
+    }
+}
+
+#[test]
+fn fixed_records() {
+    // Records are split by size only, flushes of processor don't show up
+    let client = ScriptedClient::new(Capabilities::all()).command(
+        ProcessingType::Clean,
+        "a.txt",
+        b"hello world",
+    );
+    let server = GitFilterServer::new(Flushing).fixed_record_size(4);
+    assert_server_transcript("fixed_records", server, client);
+}
@@ -0,0 +1,25 @@
+//! Checks streams of re-executed child, so it runs without test harness,
+//! which would write to stdout itself
+use std::process::Command;
+
+use git_filter_server::warn_user;
+
+const CHILD_ENV: &str = "GIT_FILTER_SERVER_WARN_USER_CHILD";
+
+fn main() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        warn_user("filter: something went wrong");
+        return;
+    }
+    let output = Command::new(std::env::current_exe().unwrap())
+        .env(CHILD_ENV, "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "filter: something went wrong\n"
+    );
+    // Stdout is occupied by protocol
+    assert!(output.stdout.is_empty());
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::ensure;
+use common::Careless;
+use git_filter_server::{
+    testing::{render_pkt_stream, ScriptedClient, Transcript},
+    Capabilities, GitFilterServer, Pathname, ProcessingType, Processor,
+};
+
+/// Clean should produce pointer, and smudge should resolve it
+fn server() -> GitFilterServer<Careless> {
+    GitFilterServer::new(Careless)
+        .validate_output(ProcessingType::Clean, |output| {
+            ensure!(output.starts_with(b"ptr:"), "not a pointer");
+            Ok(())
+        })
+        .validate_output(ProcessingType::Smudge, |output| {
+            ensure!(!output.starts_with(b"ptr:"), "pointer was not resolved");
+            Ok(())
+        })
+}
+
+fn response(
+    server: &mut GitFilterServer<Careless>,
+    process_type: ProcessingType,
+    content: &[u8],
+) -> String {
+    let client = ScriptedClient::new(Capabilities::all()).command(process_type, "a", content);
+    let transcript = Transcript::record(server, client).unwrap();
+    let response = render_pkt_stream(&transcript.server);
+    // Skip handshake
+    response.splitn(3, "0000\n").nth(2).unwrap().to_owned()
+}
+
+#[test]
+fn valid_output_is_sent() {
+    let mut server = server();
+    assert_eq!(
+        response(&mut server, ProcessingType::Clean, b"ptr:1"),
+        "0013 status=success\\n\n0000\n0009 ptr:1\n0000\n0000\n"
+    );
+    assert_eq!(
+        response(&mut server, ProcessingType::Smudge, b"data"),
+        "0013 status=success\\n\n0000\n0008 data\n0000\n0000\n"
+    );
+    assert!(server.summary().errors.is_empty());
+}
+
+#[test]
+fn invalid_clean_output_is_never_sent() {
+    let mut server = server();
+    assert_eq!(
+        response(&mut server, ProcessingType::Clean, b"data"),
+        "0011 status=error\\n\n0000\n"
+    );
+    assert!(server.summary().errors[0]
+        .reason
+        .contains("output validation failed: not a pointer"));
+}
+
+#[test]
+fn invalid_smudge_output_is_never_sent() {
+    let mut server = server();
+    assert_eq!(
+        response(&mut server, ProcessingType::Smudge, b"ptr:1"),
+        "0011 status=error\\n\n0000\n"
+    );
+    assert!(server.summary().errors[0]
+        .reason
+        .contains("pointer was not resolved"));
+}
+
+/// Writes output byte by byte, counting accepted writes
+struct Counting(Arc<AtomicUsize>);
+
+impl Processor for Counting {
+    fn process<R: Read, W: Write>(
+        &mut self,
+        _pathname: &Pathname,
+        _process_type: ProcessingType,
+        input: &mut R,
+        output: &mut W,
+    ) -> anyhow::Result<()> {
+        io::copy(input, &mut io::sink())?;
+        for _ in 0..1024 * 1024 {
+            output.write_all(b"x")?;
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn supports_processing(&self, _process_type: ProcessingType) -> bool {
+        true
+    }
+}
+
+#[test]
+fn validated_output_is_bounded_by_max_output_size() {
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let mut server = GitFilterServer::new(Counting(accepted.clone()))
+        .max_output_size(8)
+        .validate_output(ProcessingType::Smudge, |_| Ok(()));
+    let client = ScriptedClient::new(Capabilities::all()).command(ProcessingType::Smudge, "a", b"");
+    Transcript::record(&mut server, client).unwrap();
+    // Processor is stopped at the limit, instead of whole output being buffered
+    assert_eq!(accepted.load(Ordering::Relaxed), 8);
+    assert!(server.summary().errors[0]
+        .reason
+        .contains("max output size exceeded"));
+}
@@ -0,0 +1,194 @@
+use anyhow::{bail, Result};
+use git_filter_server::{
+    Pathname, ProcessingType, Processor, WasmInstance, WasmLimits, WasmProcessor, WasmiInstance,
+};
+
+const BUFFER: usize = 8;
+const BUFFER_SIZE: usize = 16;
+
+/// Module implementing ABI natively: clean uppercases content, and prefixes it with pathname
+#[derive(Default)]
+struct UppercaseModule {
+    memory: Vec<u8>,
+    pending: Vec<u8>,
+    fail_input: bool,
+    calls: Vec<String>,
+}
+
+impl UppercaseModule {
+    fn new() -> Self {
+        Self {
+            memory: vec![0; 64],
+            ..Self::default()
+        }
+    }
+}
+
+impl WasmInstance for UppercaseModule {
+    fn call(&mut self, name: &str, args: &[i32]) -> Result<i32> {
+        self.calls.push(name.to_owned());
+        let buffer = BUFFER..BUFFER + BUFFER_SIZE;
+        Ok(match (name, args) {
+            ("filter_buffer", []) => BUFFER as i32,
+            ("filter_buffer_size", []) => BUFFER_SIZE as i32,
+            ("filter_supports", [op]) => (*op == 0) as i32,
+            ("filter_begin", [0, len]) => {
+                self.pending = self.memory[buffer][..*len as usize].to_vec();
+                self.pending.push(b':');
+                0
+            }
+            ("filter_input", [_]) if self.fail_input => -1,
+            ("filter_input", [len]) => {
+                let chunk = &self.memory[buffer][..*len as usize];
+                self.pending.extend(chunk.to_ascii_uppercase());
+                0
+            }
+            ("filter_finish", []) => 0,
+            ("filter_output", []) => {
+                let len = self.pending.len().min(BUFFER_SIZE);
+                let chunk: Vec<u8> = self.pending.drain(..len).collect();
+                self.memory[BUFFER..BUFFER + len].copy_from_slice(&chunk);
+                len as i32
+            }
+            _ => bail!("unexpected call {}{:?}", name, args),
+        })
+    }
+
+    fn read_memory(&mut self, offset: u32, buf: &mut [u8]) -> Result<()> {
+        let offset = offset as usize;
+        buf.copy_from_slice(&self.memory[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    fn write_memory(&mut self, offset: u32, data: &[u8]) -> Result<()> {
+        let offset = offset as usize;
+        self.memory[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+#[test]
+fn supported_operations_are_queried_from_module() {
+    let processor = WasmProcessor::new(UppercaseModule::new()).unwrap();
+    assert!(processor.supports_processing(ProcessingType::Clean));
+    assert!(!processor.supports_processing(ProcessingType::Smudge));
+}
+
+#[test]
+fn content_is_streamed_through_transfer_buffer() {
+    let mut processor = WasmProcessor::new(UppercaseModule::new()).unwrap();
+    // Longer than transfer buffer, so both directions take several chunks
+    let input = b"the quick brown fox jumps over the lazy dog";
+    let mut output = Vec::new();
+    processor
+        .process(
+            &Pathname::from("a.txt"),
+            ProcessingType::Clean,
+            &mut &input[..],
+            &mut output,
+        )
+        .unwrap();
+    assert_eq!(output, b"a.txt:THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG");
+
+    let calls = processor.into_instance().calls;
+    let count = |name| calls.iter().filter(|c| *c == name).count();
+    assert_eq!(count("filter_input"), 3);
+    // Output chunks, and the final empty one
+    assert_eq!(count("filter_output"), 5);
+}
+
+#[test]
+fn module_failure_fails_file() {
+    let mut module = UppercaseModule::new();
+    module.fail_input = true;
+    let mut processor = WasmProcessor::new(module).unwrap();
+    let mut output = Vec::new();
+    let e = processor
+        .process(
+            &Pathname::from("a.txt"),
+            ProcessingType::Clean,
+            &mut &b"data"[..],
+            &mut output,
+        )
+        .unwrap_err();
+    assert_eq!(e.to_string(), "filter_input failed with status -1");
+    assert!(output.is_empty());
+}
+
+#[test]
+fn pathname_larger_than_buffer_is_rejected() {
+    let mut processor = WasmProcessor::new(UppercaseModule::new()).unwrap();
+    let pathname = Pathname::from("a/very/long/pathname.txt");
+    let result = processor.process(
+        &pathname,
+        ProcessingType::Clean,
+        &mut &b""[..],
+        &mut Vec::new(),
+    );
+    assert!(result.is_err());
+}
+
+fn load(wat: &str, limits: WasmLimits) -> Result<WasmProcessor<WasmiInstance>> {
+    WasmProcessor::load(&wat::parse_str(wat).unwrap(), limits)
+}
+
+fn clean<P: Processor>(processor: &mut P, pathname: &str, input: &[u8]) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    processor.process(
+        &Pathname::from(pathname),
+        ProcessingType::Clean,
+        &mut &input[..],
+        &mut output,
+    )?;
+    Ok(output)
+}
+
+#[test]
+fn module_is_run_in_wasmi() {
+    let mut processor = load(include_str!("wasm/uppercase.wat"), WasmLimits::default()).unwrap();
+    assert!(processor.supports_processing(ProcessingType::Clean));
+    assert!(!processor.supports_processing(ProcessingType::Smudge));
+    let input = b"the quick brown fox jumps over the lazy dog";
+    assert_eq!(
+        clean(&mut processor, "a.txt", input).unwrap(),
+        b"a.txt:THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG"
+    );
+    // State of previous file doesn't leak into the next one
+    assert_eq!(clean(&mut processor, "b", b"xyz").unwrap(), b"b:XYZ");
+}
+
+#[test]
+fn module_running_out_of_fuel_fails_file() {
+    let limits = WasmLimits {
+        fuel_per_call: Some(100_000),
+        ..WasmLimits::default()
+    };
+    let mut processor = load(include_str!("wasm/spinning.wat"), limits).unwrap();
+    let e = clean(&mut processor, "a", b"data").unwrap_err();
+    assert_eq!(e.to_string(), "filter_input trapped");
+}
+
+#[test]
+fn module_with_imports_is_rejected() {
+    let wat = r#"(module (import "env" "log" (func)) (memory (export "memory") 1))"#;
+    let e = load(wat, WasmLimits::default()).err().unwrap();
+    assert_eq!(
+        e.to_string(),
+        "module imports env::log, but no imports are provided"
+    );
+}
+
+#[test]
+fn module_memory_is_limited() {
+    let wat = r#"(module (memory (export "memory") 1)
+        (func (export "filter_buffer") (result i32) (i32.const 0))
+        (func (export "filter_buffer_size") (result i32)
+            (memory.grow (i32.const 2))))"#;
+    let limits = WasmLimits {
+        max_memory: 2 * 65536,
+        ..WasmLimits::default()
+    };
+    // Growth is refused, so module reports failure as buffer size
+    let e = load(wat, limits).err().unwrap();
+    assert_eq!(e.to_string(), "bad transfer buffer size: -1");
+}
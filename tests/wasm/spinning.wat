@@ -0,0 +1,13 @@
+;; Supports clean, but never finishes processing input
+(module
+  (memory (export "memory") 1)
+  (func (export "filter_buffer") (result i32) (i32.const 0))
+  (func (export "filter_buffer_size") (result i32) (i32.const 16))
+  (func (export "filter_supports") (param i32) (result i32) (i32.const 1))
+  (func (export "filter_begin") (param i32 i32) (result i32) (i32.const 0))
+  (func (export "filter_input") (param i32) (result i32)
+    (loop $spin (br $spin))
+    (i32.const 0))
+  (func (export "filter_finish") (result i32) (i32.const 0))
+  (func (export "filter_output") (result i32) (i32.const 0))
+)
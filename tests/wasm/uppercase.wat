@@ -0,0 +1,71 @@
+;; Clean uppercases content and prefixes it with pathname, smudge is unsupported
+;;
+;; Transfer buffer is at 1024, output is collected at 4096 until it is requested
+(module
+  (memory (export "memory") 1)
+  (global $out_len (mut i32) (i32.const 0))
+  (global $out_pos (mut i32) (i32.const 0))
+
+  (func (export "filter_buffer") (result i32) (i32.const 1024))
+  (func (export "filter_buffer_size") (result i32) (i32.const 16))
+
+  (func (export "filter_supports") (param $op i32) (result i32)
+    (i32.eqz (local.get $op)))
+
+  ;; Append byte to collected output
+  (func $push (param $byte i32)
+    (i32.store8 (i32.add (i32.const 4096) (global.get $out_len)) (local.get $byte))
+    (global.set $out_len (i32.add (global.get $out_len) (i32.const 1))))
+
+  (func (export "filter_begin") (param $op i32) (param $len i32) (result i32)
+    (local $i i32)
+    (if (local.get $op) (then (return (i32.const -1))))
+    (global.set $out_len (i32.const 0))
+    (global.set $out_pos (i32.const 0))
+    (block $done
+      (loop $copy
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (call $push (i32.load8_u (i32.add (i32.const 1024) (local.get $i))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $copy)))
+    (call $push (i32.const 58))
+    (i32.const 0))
+
+  (func (export "filter_input") (param $len i32) (result i32)
+    (local $i i32)
+    (local $byte i32)
+    ;; Output must fit in the rest of single page
+    (if (i32.gt_u (i32.add (global.get $out_len) (local.get $len)) (i32.const 61440))
+      (then (return (i32.const -1))))
+    (block $done
+      (loop $copy
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (local.set $byte (i32.load8_u (i32.add (i32.const 1024) (local.get $i))))
+        (if (i32.and
+              (i32.ge_u (local.get $byte) (i32.const 97))
+              (i32.le_u (local.get $byte) (i32.const 122)))
+          (then (local.set $byte (i32.sub (local.get $byte) (i32.const 32)))))
+        (call $push (local.get $byte))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $copy)))
+    (i32.const 0))
+
+  (func (export "filter_finish") (result i32) (i32.const 0))
+
+  (func (export "filter_output") (result i32)
+    (local $len i32)
+    (local $i i32)
+    (local.set $len (i32.sub (global.get $out_len) (global.get $out_pos)))
+    (if (i32.gt_u (local.get $len) (i32.const 16))
+      (then (local.set $len (i32.const 16))))
+    (block $done
+      (loop $copy
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (i32.store8
+          (i32.add (i32.const 1024) (local.get $i))
+          (i32.load8_u (i32.add (i32.const 4096) (i32.add (global.get $out_pos) (local.get $i)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $copy)))
+    (global.set $out_pos (i32.add (global.get $out_pos) (local.get $len)))
+    (local.get $len))
+)